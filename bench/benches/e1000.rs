@@ -0,0 +1,279 @@
+// Throughput of the transmit and receive paths over bursts of descriptors,
+// guest memory is a flat buffer so the emulation itself dominates
+//
+// Throughput is reported in frames per second by criterion, DMA operations per frame are
+// counted on a single burst beforehand and printed, as they are not measured by criterion
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use nic_emu::e1000::{ResetReason, E1000};
+use nic_emu::NicContext;
+
+const MEMORY_SIZE: usize = 0x100000;
+const RING_SIZE: usize = 256;
+const DESCRIPTOR_LENGTH: usize = 16;
+
+// Guest memory layout
+const TX_RING_ADDRESS: usize = 0x0;
+const RX_RING_ADDRESS: usize = 0x1000;
+const TX_BUFFER_ADDRESS: usize = 0x10000;
+const RX_BUFFER_ADDRESS: usize = 0x40000;
+const RX_BUFFER_SIZE: usize = 2048; // Default of RCTL.BSIZE
+
+// Registers
+const RCTL: usize = 0x100;
+const TCTL: usize = 0x400;
+const RDBAL: usize = 0x2800;
+const RDLEN: usize = 0x2808;
+const RDT: usize = 0x2818;
+const TDBAL: usize = 0x3800;
+const TDLEN: usize = 0x3808;
+const TDT: usize = 0x3818;
+const RCTL_EN: u32 = 1 << 1;
+const TCTL_EN: u32 = 1 << 1;
+
+// Descriptor command bits
+const CMD_EOP: u8 = 1 << 0;
+const CMD_IFCS: u8 = 1 << 1;
+const CMD_TSE: u8 = 1 << 2;
+const CMD_RS: u8 = 1 << 3;
+const CMD_DEXT: u8 = 1 << 5;
+const TUCMD_TCP: u8 = 1 << 0;
+const TUCMD_IP: u8 = 1 << 1;
+const POPTS_IXSM: u8 = 1 << 0;
+const POPTS_TXSM: u8 = 1 << 1;
+
+const SMALL_FRAME_LENGTH: usize = 60;
+const FULL_FRAME_LENGTH: usize = 1514;
+const TSO_HEADER_LENGTH: usize = 54; // Ethernet, IPv4 and TCP without options
+const TSO_PAYLOAD_LENGTH: usize = 16384;
+const TSO_MSS: usize = 1448;
+
+#[derive(Default)]
+struct Counters {
+    dma_reads: usize,
+    dma_writes: usize,
+    frames_sent: usize,
+}
+
+struct MockNicContext {
+    memory: Vec<u8>,
+    counters: Counters,
+}
+
+impl NicContext for MockNicContext {
+    fn send(&mut self, buffer: &[u8]) -> anyhow::Result<usize> {
+        self.counters.frames_sent += 1;
+        Ok(buffer.len())
+    }
+
+    fn dma_read(&mut self, address: usize, buffer: &mut [u8], offset: usize) {
+        self.counters.dma_reads += 1;
+        let start = address + offset;
+        buffer.copy_from_slice(&self.memory[start..start + buffer.len()]);
+    }
+
+    fn dma_write(&mut self, address: usize, buffer: &[u8], offset: usize) {
+        self.counters.dma_writes += 1;
+        let start = address + offset;
+        self.memory[start..start + buffer.len()].copy_from_slice(buffer);
+    }
+
+    fn trigger_interrupt(&mut self) {}
+}
+
+// Device with the driver side of both rings, tails are advanced by a burst at a time
+struct Device {
+    e1000: E1000<MockNicContext>,
+    tx_tail: usize,
+    rx_tail: usize,
+}
+
+impl Device {
+    fn new() -> Self {
+        let nic_ctx = MockNicContext {
+            memory: vec![0u8; MEMORY_SIZE],
+            counters: Default::default(),
+        };
+        let mut e1000 = E1000::new(nic_ctx, false);
+        e1000.eeprom.pack_initial_eeprom();
+        e1000.reset_e1000(ResetReason::FunctionLevel);
+
+        Device {
+            e1000,
+            tx_tail: 0,
+            rx_tail: 0,
+        }
+    }
+
+    fn write_register(&mut self, offset: usize, value: u32) {
+        let mut data = value.to_le_bytes();
+        self.e1000
+            .region_access_bar0(offset, &mut data, true)
+            .unwrap();
+    }
+
+    fn write_memory(&mut self, address: usize, data: &[u8]) {
+        self.e1000.nic_ctx.memory[address..address + data.len()].copy_from_slice(data);
+    }
+
+    fn setup_tx_ring(&mut self, descriptors: &[[u8; DESCRIPTOR_LENGTH]]) {
+        for index in 0..RING_SIZE {
+            let descriptor = &descriptors[index % descriptors.len()];
+            self.write_memory(TX_RING_ADDRESS + index * DESCRIPTOR_LENGTH, descriptor);
+        }
+        self.write_register(TDBAL, TX_RING_ADDRESS as u32);
+        self.write_register(TDLEN, (RING_SIZE * DESCRIPTOR_LENGTH) as u32);
+        self.write_register(TCTL, TCTL_EN);
+    }
+
+    // Every descriptor owns its buffer, all but one are handed to the device
+    fn setup_rx_ring(&mut self) {
+        for index in 0..RING_SIZE {
+            let buffer = (RX_BUFFER_ADDRESS + index * RX_BUFFER_SIZE) as u64;
+            self.write_memory(
+                RX_RING_ADDRESS + index * DESCRIPTOR_LENGTH,
+                &buffer.to_le_bytes(),
+            );
+        }
+        self.write_register(RDBAL, RX_RING_ADDRESS as u32);
+        self.write_register(RDLEN, (RING_SIZE * DESCRIPTOR_LENGTH) as u32);
+        self.rx_tail = RING_SIZE - 1;
+        self.write_register(RDT, self.rx_tail as u32);
+        self.write_register(RCTL, RCTL_EN);
+    }
+
+    fn transmit_burst(&mut self, descriptors: usize) {
+        self.tx_tail = (self.tx_tail + descriptors) % RING_SIZE;
+        self.write_register(TDT, self.tx_tail as u32);
+    }
+
+    // Receive frames, then return their descriptors like a driver would after processing them
+    fn receive_burst(&mut self, frame: &[u8], frames: usize) {
+        for _ in 0..frames {
+            self.e1000.receive(black_box(frame)).unwrap();
+        }
+        self.rx_tail = (self.rx_tail + frames) % RING_SIZE;
+        self.write_register(RDT, self.rx_tail as u32);
+    }
+
+    // Run a single burst and print DMA operations per frame
+    fn report_dma_operations(&mut self, name: &str, frames: usize, burst: impl FnOnce(&mut Self)) {
+        self.e1000.nic_ctx.counters = Default::default();
+        burst(self);
+        let counters = &self.e1000.nic_ctx.counters;
+        println!(
+            "{}: {:.2} DMA reads/frame, {:.2} DMA writes/frame",
+            name,
+            counters.dma_reads as f64 / frames as f64,
+            counters.dma_writes as f64 / frames as f64
+        );
+    }
+}
+
+fn legacy_descriptor(address: usize, length: usize) -> [u8; DESCRIPTOR_LENGTH] {
+    let mut descriptor = [0u8; DESCRIPTOR_LENGTH];
+    descriptor[0..8].copy_from_slice(&(address as u64).to_le_bytes());
+    descriptor[8..10].copy_from_slice(&(length as u16).to_le_bytes());
+    descriptor[11] = CMD_EOP | CMD_IFCS | CMD_RS;
+    descriptor
+}
+
+// TCP segmentation context for the TSO frame header
+fn tso_context_descriptor() -> [u8; DESCRIPTOR_LENGTH] {
+    let mut descriptor = [0u8; DESCRIPTOR_LENGTH];
+    descriptor[0] = 14; // IPCSS
+    descriptor[1] = 14 + 10; // IPCSO
+    descriptor[2..4].copy_from_slice(&(14 + 20 - 1u16).to_le_bytes()); // IPCSE
+    descriptor[4] = 34; // TUCSS
+    descriptor[5] = 34 + 16; // TUCSO
+    descriptor[8..12].copy_from_slice(&(TSO_PAYLOAD_LENGTH as u32).to_le_bytes()); // PAYLEN, DTYP 0
+    descriptor[11] = TUCMD_TCP | TUCMD_IP | CMD_TSE | CMD_DEXT;
+    descriptor[13] = TSO_HEADER_LENGTH as u8;
+    descriptor[14..16].copy_from_slice(&(TSO_MSS as u16).to_le_bytes());
+    descriptor
+}
+
+fn tso_data_descriptor(address: usize) -> [u8; DESCRIPTOR_LENGTH] {
+    let length = (TSO_HEADER_LENGTH + TSO_PAYLOAD_LENGTH) as u32 | 1 << 20; // DTYP 1
+    let mut descriptor = [0u8; DESCRIPTOR_LENGTH];
+    descriptor[0..8].copy_from_slice(&(address as u64).to_le_bytes());
+    descriptor[8..12].copy_from_slice(&length.to_le_bytes());
+    descriptor[11] = CMD_EOP | CMD_IFCS | CMD_TSE | CMD_RS | CMD_DEXT;
+    descriptor[13] = POPTS_IXSM | POPTS_TXSM;
+    descriptor
+}
+
+// Broadcast IPv4/TCP frame, checksums are left to the device or not checked at all
+fn frame(length: usize) -> Vec<u8> {
+    let mut frame = vec![0u8; length];
+    frame[0..6].fill(0xFF);
+    frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+    frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+    frame[14] = 0x45;
+    frame[16..18].copy_from_slice(&((length - 14) as u16).to_be_bytes());
+    frame[22] = 64; // TTL
+    frame[23] = 6; // TCP
+    frame[26..30].copy_from_slice(&[10, 0, 0, 1]);
+    frame[30..34].copy_from_slice(&[10, 0, 0, 2]);
+    frame[34..36].copy_from_slice(&1000u16.to_be_bytes());
+    frame[36..38].copy_from_slice(&2000u16.to_be_bytes());
+    frame[46] = 5 << 4; // Data offset
+    frame[47] = 0x18; // ACK, PSH
+    frame
+}
+
+fn transmit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transmit");
+
+    let burst = 64;
+    let mut device = Device::new();
+    device.write_memory(TX_BUFFER_ADDRESS, &frame(SMALL_FRAME_LENGTH));
+    device.setup_tx_ring(&[legacy_descriptor(TX_BUFFER_ADDRESS, SMALL_FRAME_LENGTH)]);
+    device.report_dma_operations("transmit/small", burst, |device| {
+        device.transmit_burst(burst)
+    });
+    group.throughput(Throughput::Elements(burst as u64));
+    group.bench_function("small", |b| b.iter(|| device.transmit_burst(burst)));
+
+    // Context and data descriptor per TSO frame, each split into segments of MSS
+    let burst = 8;
+    let segments = TSO_PAYLOAD_LENGTH.div_ceil(TSO_MSS);
+    let mut device = Device::new();
+    device.write_memory(
+        TX_BUFFER_ADDRESS,
+        &frame(TSO_HEADER_LENGTH + TSO_PAYLOAD_LENGTH),
+    );
+    device.setup_tx_ring(&[
+        tso_context_descriptor(),
+        tso_data_descriptor(TX_BUFFER_ADDRESS),
+    ]);
+    device.report_dma_operations("transmit/tso", burst * segments, |device| {
+        device.transmit_burst(burst * 2)
+    });
+    assert_eq!(device.e1000.nic_ctx.counters.frames_sent, burst * segments);
+    group.throughput(Throughput::Elements((burst * segments) as u64));
+    group.bench_function("tso", |b| b.iter(|| device.transmit_burst(burst * 2)));
+
+    group.finish();
+}
+
+fn receive(c: &mut Criterion) {
+    let mut group = c.benchmark_group("receive");
+
+    let burst = 64;
+    for (name, length) in [("small", SMALL_FRAME_LENGTH), ("full", FULL_FRAME_LENGTH)] {
+        let frame = frame(length);
+        let mut device = Device::new();
+        device.setup_rx_ring();
+        device.report_dma_operations(&format!("receive/{}", name), burst, |device| {
+            device.receive_burst(&frame, burst)
+        });
+        group.throughput(Throughput::Elements(burst as u64));
+        group.bench_function(name, |b| b.iter(|| device.receive_burst(&frame, burst)));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, transmit, receive);
+criterion_main!(benches);
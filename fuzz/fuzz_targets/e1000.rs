@@ -0,0 +1,151 @@
+#![no_main]
+
+// Interprets input as a sequence of register accesses and injected frames,
+// the device must handle any of them without panicking
+//
+// Operations, all integers little endian:
+// 0x00 offset:u16 value:u32 -> bar0 write at offset * 4
+// 0x01 offset:u16           -> bar0 read at offset * 4
+// 0x02 length:u16 data      -> receive frame of length % 2048 bytes, in any receive state
+// 0x03                      -> timer elapsed
+// 0x04 address:u16 data:u32 -> write guest memory, e.g. to fill descriptors
+// 0x05 delay:u8             -> delay all following dma reads by delay microseconds,
+//                              advancing the device clock instead of sleeping
+// 0x06                      -> corrupt next dma read, e.g. of a descriptor, by inverting the data
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use libfuzzer_sys::fuzz_target;
+use nic_emu::e1000::{ResetReason, E1000};
+use nic_emu::{Clock, NicContext};
+
+// Small guest memory, all dma addresses wrap around inside it
+const MEMORY_SIZE: usize = 0x10000;
+const BAR0_SIZE: usize = 0x20000;
+const MAX_FRAME_LENGTH: usize = 2048;
+
+// Device time, advanced by slow dma reads so timers observe their latency
+#[derive(Clone)]
+struct MockClock {
+    start: Instant,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+}
+
+// Guest memory may be slow or change under the device, reads must not be assumed consistent
+struct MockNicContext {
+    memory: Vec<u8>,
+    clock: MockClock,
+    dma_read_delay: Duration,
+    corrupt_next_read: bool,
+}
+
+impl NicContext for MockNicContext {
+    fn send(&mut self, buffer: &[u8]) -> anyhow::Result<usize> {
+        Ok(buffer.len())
+    }
+
+    fn dma_read(&mut self, address: usize, buffer: &mut [u8], offset: usize) {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.memory[address.wrapping_add(offset + i) % MEMORY_SIZE];
+        }
+
+        let elapsed = &self.clock.elapsed;
+        elapsed.set(elapsed.get() + self.dma_read_delay);
+        if self.corrupt_next_read {
+            self.corrupt_next_read = false;
+            buffer.iter_mut().for_each(|byte| *byte = !*byte);
+        }
+    }
+
+    fn dma_write(&mut self, address: usize, buffer: &[u8], offset: usize) {
+        for (i, byte) in buffer.iter().enumerate() {
+            self.memory[address.wrapping_add(offset + i) % MEMORY_SIZE] = *byte;
+        }
+    }
+
+    fn trigger_interrupt(&mut self) {}
+}
+
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<[u8; 4]> {
+        Some(self.take(4)?.try_into().unwrap())
+    }
+}
+
+fn run(e1000: &mut E1000<MockNicContext>, input: &mut Input) -> Option<()> {
+    loop {
+        match input.u8()? {
+            0x00 => {
+                let offset = input.u16()? as usize * 4 % BAR0_SIZE;
+                let mut value = input.u32()?;
+                let _ = e1000.region_access_bar0(offset, &mut value, true);
+            }
+            0x01 => {
+                let offset = input.u16()? as usize * 4 % BAR0_SIZE;
+                let _ = e1000.region_access_bar0(offset, &mut [0u8; 4], false);
+            }
+            0x02 => {
+                let length = input.u16()? as usize % MAX_FRAME_LENGTH;
+                let frame = input.take(length)?;
+                let _ = e1000.receive(frame);
+            }
+            0x03 => e1000.timer_elapsed(),
+            0x04 => {
+                let address = input.u16()? as usize;
+                let data = input.u32()?;
+                e1000.nic_ctx.dma_write(address, &data, 0);
+            }
+            0x05 => {
+                e1000.nic_ctx.dma_read_delay = Duration::from_micros(input.u8()? as u64);
+            }
+            0x06 => e1000.nic_ctx.corrupt_next_read = true,
+            _ => {}
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let clock = MockClock {
+        start: Instant::now(),
+        elapsed: Default::default(),
+    };
+    let nic_ctx = MockNicContext {
+        memory: vec![0u8; MEMORY_SIZE],
+        clock: clock.clone(),
+        dma_read_delay: Duration::ZERO,
+        corrupt_next_read: false,
+    };
+    let mut e1000 = E1000::new(nic_ctx, true);
+    e1000.set_clock(Box::new(clock));
+    e1000.eeprom.pack_initial_eeprom();
+    e1000.reset_e1000(ResetReason::FunctionLevel);
+
+    run(&mut e1000, &mut Input(data));
+});
@@ -1,7 +1,6 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use libvfio_user::dma::DmaMapping;
@@ -17,18 +16,7 @@ pub struct LibvfioUserContext {
     // Cache dma mappings instead of releasing them after each op
     dma_mappings: HashMap<usize, DmaMapping>,
 
-    // Keep track of requested timer and update real timer in main
-    pub timer: Option<Instant>,
-    pub timer_has_changed: bool,
-
     pub interface: Option<Interface>, // will be set later
-
-    // Statistics
-    pub interrupt_count: u64,
-    pub dma_read_count: u64,
-    pub dma_read_bytes: u64,
-    pub dma_write_count: u64,
-    pub dma_write_bytes: u64,
 }
 
 impl NicContext for LibvfioUserContext {
@@ -69,8 +57,6 @@ impl NicContext for LibvfioUserContext {
             .expect("Missing dma mapping, dma_prepare is probably missing");
 
         mapping.read_into_volatile(0, buffer, offset).unwrap();
-        self.dma_read_count += 1;
-        self.dma_read_bytes += buffer.len() as u64;
     }
 
     fn dma_write(&mut self, address: usize, buffer: &[u8], offset: usize) {
@@ -81,23 +67,10 @@ impl NicContext for LibvfioUserContext {
             .expect("Missing dma mapping, dma_prepare is probably missing");
 
         mapping.write_volatile(0, buffer, offset).unwrap();
-        self.dma_write_count += 1;
-        self.dma_write_bytes += buffer.len() as u64;
     }
 
     fn trigger_interrupt(&mut self) {
         self.device_context.trigger_irq(0).unwrap();
-        self.interrupt_count += 1;
-    }
-
-    fn set_timer(&mut self, duration: Duration) {
-        self.timer = Some(Instant::now() + duration);
-        self.timer_has_changed = true;
-    }
-
-    fn delete_timer(&mut self) {
-        self.timer = None;
-        self.timer_has_changed = true;
     }
 }
 
@@ -106,14 +79,7 @@ impl LibvfioUserContext {
         LibvfioUserContext {
             device_context,
             dma_mappings: Default::default(),
-            timer: None,
-            timer_has_changed: false,
             interface: None,
-            interrupt_count: 0,
-            dma_read_count: 0,
-            dma_read_bytes: 0,
-            dma_write_count: 0,
-            dma_write_bytes: 0,
         }
     }
 }
@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use libvfio_user::*;
@@ -7,11 +8,34 @@ use log::{debug, error, info, log, Level};
 use macaddr::MacAddr6;
 
 use crate::ctx::LibvfioUserContext;
-use nic_emu::e1000::E1000;
+use nic_emu::e1000::{ResetReason, E1000};
+
+// Default mac x2-... is in locally administered range and
+// should hopefully not conflict with anything
+const DEFAULT_MAC: MacAddr6 = MacAddr6::new(0x02, 0x34, 0x56, 0x78, 0x9A, 0xBC);
+
+/// PCI resources exposed to the guest, defaults are those of the 82540EM
+#[derive(Debug, Clone, Copy)]
+pub struct PciResources {
+    pub bar0_size: usize,
+    pub msi_vectors: u32,
+    pub msix_vectors: u32, // 0 to not expose MSI-X, the only value currently supported
+}
+
+impl Default for PciResources {
+    fn default() -> Self {
+        PciResources {
+            bar0_size: 0x20000, // 128 KiB
+            msi_vectors: 1,
+            msix_vectors: 0,
+        }
+    }
+}
 
 // Device facing libvfio_user for callbacks, forwarding them to behavioral model
 pub struct E1000Device {
     pub e1000: E1000<LibvfioUserContext>,
+    pub started: Instant, // Rates in the final statistics are relative to it
 }
 
 impl Device for E1000Device {
@@ -20,6 +44,7 @@ impl Device for E1000Device {
 
         E1000Device {
             e1000: E1000::new(nic_ctx, true),
+            started: Instant::now(),
         }
     }
 
@@ -42,7 +67,13 @@ impl Device for E1000Device {
 
     fn reset(&mut self, reason: DeviceResetReason) -> Result<(), i32> {
         info!("Resetting device, Reason: {:?}", reason);
-        self.e1000.reset_e1000();
+        // Client requested resets are the equivalent of an FLR on VFIO devices
+        match reason {
+            DeviceResetReason::ClientRequest | DeviceResetReason::PciReset => {
+                self.e1000.reset_e1000(ResetReason::FunctionLevel)
+            }
+            DeviceResetReason::LostConnection => self.e1000.on_detach(),
+        }
         Ok(())
     }
 
@@ -70,7 +101,26 @@ impl Device for E1000Device {
 }
 
 impl E1000Device {
-    pub fn build(path: PathBuf, mac: MacAddr6) -> Box<Self> {
+    /// Eeprom is synthesized from the default or given mac unless an eeprom image is given,
+    /// in which case the mac only overrides the one in the image if given
+    pub fn build(
+        path: PathBuf, mac: Option<MacAddr6>, eeprom_image: Option<Vec<u8>>,
+        mitigate_interrupts: bool, min_itr_interval: Option<Duration>, resources: PciResources,
+    ) -> Box<Self> {
+        // BARs are naturally aligned, so their size has to be a power of two
+        if !resources.bar0_size.is_power_of_two() {
+            error!("BAR0 size {:#x} is not a power of two", resources.bar0_size);
+            std::process::exit(1);
+        }
+        // The 82540EM has no MSI-X, vectors would never be triggered as interrupts go to IRQ 0
+        if resources.msix_vectors != 0 {
+            error!(
+                "{} MSI-X vectors requested, MSI-X is not supported by the emulated 82540EM",
+                resources.msix_vectors
+            );
+            std::process::exit(1);
+        }
+
         let config = DeviceConfigurator::default()
             .socket_path(path)
             .overwrite_socket(true)
@@ -87,7 +137,7 @@ impl E1000Device {
             })
             .add_device_region(DeviceRegion {
                 region_type: DeviceRegionKind::Bar0,
-                size: 0x20000, // 128 KiB
+                size: resources.bar0_size,
                 file_descriptor: -1,
                 offset: 0,
                 read: true,
@@ -104,7 +154,8 @@ impl E1000Device {
                 memory: false,
             })
             .using_interrupt_requests(InterruptRequestKind::IntX, 1)
-            .using_interrupt_requests(InterruptRequestKind::Msi, 1)
+            .using_interrupt_requests(InterruptRequestKind::Msi, resources.msi_vectors)
+            .using_interrupt_requests(InterruptRequestKind::Msix, resources.msix_vectors)
             .setup_dma(true)
             .non_blocking(true)
             .build()
@@ -113,15 +164,85 @@ impl E1000Device {
         let mut e1000_device = config.produce::<E1000Device>().unwrap();
         debug!("VFU context created successfully");
 
+        // Registers beyond BAR0 would be unreachable for the driver
+        let registers = e1000_device.e1000.implemented_registers();
+        let required_size = registers
+            .last()
+            .map_or(0, |(offset, _)| *offset as usize + 4);
+        if resources.bar0_size < required_size {
+            error!(
+                "BAR0 size {:#x} too small, implemented registers need {:#x}",
+                resources.bar0_size, required_size
+            );
+            std::process::exit(1);
+        }
+
         // TODO: Move this inside E1000 constructor, would require changes to libvfio-user-rs
         // Setup initial eeprom, should not be changed afterwards
+        let eeprom = &mut e1000_device.e1000.eeprom;
+        match eeprom_image {
+            Some(image) => {
+                if let Err(err) = eeprom.load_image(&image) {
+                    error!("Invalid eeprom image: {:#}", err);
+                    std::process::exit(1);
+                }
+                if !eeprom.verify_checksum() {
+                    error!(
+                        "Eeprom image checksum is invalid, words sum up to {:x} instead of baba",
+                        eeprom.checksum()
+                    );
+                    std::process::exit(1);
+                }
+                if let Some(mac) = mac {
+                    eeprom.set_ethernet_address(mac.into_array());
+                }
+            }
+            None => {
+                eeprom
+                    .initial_eeprom
+                    .set_ethernet_address(mac.unwrap_or(DEFAULT_MAC).into_array());
+                eeprom.pack_initial_eeprom();
+            }
+        }
+        info!(
+            "Using ethernet address {}",
+            MacAddr6::from(eeprom.ethernet_address())
+        );
+
+        // Device::new is called by libvfio-user without our arguments, so apply them here
         e1000_device
             .e1000
-            .eeprom
-            .initial_eeprom
-            .set_ethernet_address(mac.into_array());
-        e1000_device.e1000.eeprom.pack_initial_eeprom();
+            .set_interrupt_mitigation(mitigate_interrupts);
+        e1000_device.e1000.set_min_itr_interval(min_itr_interval);
 
         e1000_device
     }
 }
+
+// Runs on regular exit and when unwinding from a panic (not with panic = "abort" in release
+// builds), dropping the nic context closes the tap interface and undoes its setup
+impl Drop for E1000Device {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed().as_secs_f32();
+        let stats = self.e1000.stats();
+        info!("Statistics:");
+        info!(
+            "{} total interrupts sent, ~{:.2} per second",
+            stats.interrupt_count,
+            stats.interrupt_count as f32 / elapsed
+        );
+        info!(
+            "{} total dma reads, ~{:.2} per second, {}B total",
+            stats.dma_read_count,
+            stats.dma_read_count as f32 / elapsed,
+            stats.dma_read_bytes
+        );
+        info!(
+            "{} total dma writes, ~{:.2} per second, {}B total",
+            stats.dma_write_count,
+            stats.dma_write_count as f32 / elapsed,
+            stats.dma_write_bytes
+        );
+        info!("Exiting after {:.3}s run time.", elapsed);
+    }
+}
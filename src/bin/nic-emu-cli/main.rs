@@ -1,5 +1,6 @@
+use std::os::fd::RawFd;
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use clap::ArgAction;
 use clap::Parser;
@@ -10,13 +11,18 @@ use polling::{Event, Events, PollMode, Poller};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
 use crate::ctx::LibvfioUserContext;
-use crate::e1000::E1000Device;
-use crate::net::Interface;
-use nic_emu::e1000::E1000;
+use crate::e1000::{E1000Device, PciResources};
+use crate::net::{CommandRunner, Interface};
+use nic_emu::e1000::{ReceiveOutcome, RxReadiness, E1000};
 
 mod ctx;
 mod e1000;
 pub mod net;
+#[cfg(feature = "netlink")]
+mod netlink;
+
+// Frames received per loop iteration, so heavy ingress cannot starve guest requests and timer
+const RECEIVE_BUDGET: usize = 64;
 
 #[derive(Parser, Debug)]
 #[command(long_about = "")] // long_about required for long help, otherwise help is always short
@@ -31,16 +37,52 @@ struct Args {
     #[arg(short, long, default_value = "tap-nic-emu%d")]
     tap: String,
 
+    /// Use an already opened and set up tap interface file descriptor instead of creating one,
+    /// e.g. passed by a VMM, avoids needing CAP_NET_ADMIN
+    #[arg(long, conflicts_with_all = ["tap", "net"])]
+    tap_fd: Option<RawFd>,
+
     /// Automatically run commands to add IP range to tap interface and set link to be up,
     /// for example --net 10.1.0.1/24
     #[arg(short, long)]
     net: Option<IpNet>,
 
-    /// Ethernet address of the emulated nic inside guest
-    // Default mac x2-... is in locally administered range and
-    // should hopefully not conflict with anything
-    #[arg(short, long, default_value_t = MacAddr6::new(0x02, 0x34, 0x56, 0x78, 0x9A, 0xBC))]
-    mac: MacAddr6,
+    /// Set MTU of tap interface, larger frames sent by the guest are dropped,
+    /// only the latter if used with --tap-fd
+    #[arg(long)]
+    mtu: Option<usize>,
+
+    /// Ethernet address of the emulated nic inside guest [default: 02:34:56:78:9A:BC],
+    /// overrides the address in the eeprom image if given together with --eeprom
+    #[arg(short, long)]
+    mac: Option<MacAddr6>,
+
+    /// Raw eeprom image to load instead of synthesizing one, e.g. dumped from real hardware
+    #[arg(short, long)]
+    eeprom: Option<PathBuf>,
+
+    /// Minimum interval between interrupts in microseconds, enforced even if the guest
+    /// programs a lower interrupt throttling rate (ITR) or disables throttling
+    #[arg(long)]
+    itr: Option<u64>,
+
+    /// Disable interrupt mitigation, every interrupt is delivered right away
+    /// and receive interrupt delays are ignored
+    #[arg(long, conflicts_with = "itr")]
+    no_interrupt_mitigation: bool,
+
+    /// Size of the register BAR in bytes, a power of two covering all implemented registers
+    #[arg(long, default_value_t = PciResources::default().bar0_size)]
+    bar0_size: usize,
+
+    /// Number of MSI vectors exposed to the guest
+    #[arg(long, default_value_t = PciResources::default().msi_vectors)]
+    msi_vectors: u32,
+
+    /// Number of MSI-X vectors exposed to the guest, 0 to not expose MSI-X,
+    /// currently rejected otherwise as the emulated 82540EM has no MSI-X
+    #[arg(long, default_value_t = PciResources::default().msix_vectors)]
+    msix_vectors: u32,
 
     /// Increase verbosity, 1 time => Debug logs, multiple times => Trace logs
     #[arg(short, long, action = ArgAction::Count)]
@@ -59,9 +101,35 @@ fn main() {
         .parse_default_env() // Overwrite from RUST_LOG env var
         .init();
 
-    let mut e1000_device = E1000Device::build(args.socket, args.mac);
+    let eeprom_image = args.eeprom.map(|path| {
+        std::fs::read(&path).unwrap_or_else(|err| {
+            error!("Failed to read eeprom image {:?}: {}", path, err);
+            std::process::exit(1);
+        })
+    });
+    let min_itr_interval = args.itr.map(Duration::from_micros);
+    let mut e1000_device = E1000Device::build(
+        args.socket,
+        args.mac,
+        eeprom_image,
+        !args.no_interrupt_mitigation,
+        min_itr_interval,
+        PciResources {
+            bar0_size: args.bar0_size,
+            msi_vectors: args.msi_vectors,
+            msix_vectors: args.msix_vectors,
+        },
+    );
 
-    let interface = Interface::initialize(true, &args.tap, args.net);
+    let interface = match args.tap_fd {
+        Some(fd) => Interface::from_raw_fd(fd, true).map(|interface| interface.with_mtu(args.mtu)),
+        None => command_runner()
+            .and_then(|runner| Interface::initialize(true, &args.tap, args.net, args.mtu, runner)),
+    };
+    let interface = interface.unwrap_or_else(|err| {
+        error!("Failed to set up tap interface: {:#}", err);
+        std::process::exit(1);
+    });
     e1000_device.e1000.nic_ctx.interface = Some(interface);
 
     // Use same poller and event list for both attach and run
@@ -127,10 +195,15 @@ fn main() {
     // Buffer for received packets interface
     let mut interface_buffer = [0u8; 4096]; // Big enough
 
-    let start = Instant::now();
+    // Set if receive budget ran out, interface is only polled in Edge mode so continue without
+    // waiting for a new event
+    let mut receive_pending = false;
+
+    e1000_device.started = Instant::now();
     'polling: loop {
         events.clear();
-        poller.wait(&mut events, None).unwrap();
+        let timeout = receive_pending.then_some(Duration::ZERO);
+        poller.wait(&mut events, timeout).unwrap();
 
         // Collect which events happened and then run corresponding actions after
         // to avoid running the same action multiple times since
@@ -143,8 +216,6 @@ fn main() {
             match event.key {
                 EVENT_KEY_RUN => {
                     run = true;
-                    // Try to catch up on deferred packets (arrived during throttling)
-                    receive = true;
                 }
                 EVENT_KEY_RECEIVE => {
                     receive = true;
@@ -164,24 +235,23 @@ fn main() {
                 break 'polling;
             }
         }
-        if receive {
-            receive_packets(&mut e1000_device.e1000, &mut interface_buffer);
+        // Try to catch up on deferred packets (arrived during throttling)
+        if receive || receive_pending || e1000_device.e1000.events().receive_ready {
+            receive_pending = receive_packets(&mut e1000_device.e1000, &mut interface_buffer);
         }
         if timer_elapsed {
             // Don't trigger for old timer events that aren't needed anymore
-            if let Some(instant) = e1000_device.e1000.nic_ctx.timer {
+            if let Some(instant) = e1000_device.e1000.events().timer {
                 if instant <= Instant::now() {
                     e1000_device.e1000.timer_elapsed();
-
-                    // Reset timer to mark it as done
-                    e1000_device.e1000.nic_ctx.timer = None;
                 }
             }
         }
 
         // Update timer
-        if e1000_device.e1000.nic_ctx.timer_has_changed {
-            let duration = if let Some(change) = e1000_device.e1000.nic_ctx.timer {
+        let events = e1000_device.e1000.take_events();
+        if events.timer_changed {
+            let duration = if let Some(change) = events.timer {
                 // Check if timer has not already passed since change
                 change.checked_duration_since(Instant::now())
             } else {
@@ -193,38 +263,29 @@ fn main() {
             } else {
                 tfd.set_state(TimerState::Disarmed, SetTimeFlags::Default);
             }
-            e1000_device.e1000.nic_ctx.timer_has_changed = false;
         }
     }
     // Just let poller be dropped, delete previous fds if we want to reuse it in the future
+    // Dropping the device logs statistics and cleans up the tap interface
+}
 
-    let elapsed = start.elapsed().as_secs_f32();
-    info!("Statistics:");
-    info!(
-        "{} total interrupts sent, ~{:.2} per second",
-        e1000_device.e1000.nic_ctx.interrupt_count,
-        e1000_device.e1000.nic_ctx.interrupt_count as f32 / elapsed
-    );
-    info!(
-        "{} total dma reads, ~{:.2} per second, {}B total",
-        e1000_device.e1000.nic_ctx.dma_read_count,
-        e1000_device.e1000.nic_ctx.dma_read_count as f32 / elapsed,
-        e1000_device.e1000.nic_ctx.dma_read_bytes
-    );
-    info!(
-        "{} total dma writes, ~{:.2} per second, {}B total",
-        e1000_device.e1000.nic_ctx.dma_write_count,
-        e1000_device.e1000.nic_ctx.dma_write_count as f32 / elapsed,
-        e1000_device.e1000.nic_ctx.dma_write_bytes
-    );
-    info!("Exiting after {:.3}s run time.", elapsed);
+// Tap interfaces are configured over netlink if enabled, otherwise by spawning ip
+#[cfg(feature = "netlink")]
+fn command_runner() -> anyhow::Result<Box<dyn CommandRunner>> {
+    Ok(Box::new(netlink::NetlinkCommandRunner::new()?))
 }
 
-fn receive_packets(e1000: &mut E1000<LibvfioUserContext>, shared_buffer: &mut [u8; 4096]) {
-    loop {
-        if e1000.receive_state.should_defer() {
+#[cfg(not(feature = "netlink"))]
+fn command_runner() -> anyhow::Result<Box<dyn CommandRunner>> {
+    Ok(Box::new(net::ProcessCommandRunner))
+}
+
+// Returns true if the receive budget was used up and there may be more frames to receive
+fn receive_packets(e1000: &mut E1000<LibvfioUserContext>, shared_buffer: &mut [u8; 4096]) -> bool {
+    for _ in 0..RECEIVE_BUDGET {
+        if e1000.rx_ready() == RxReadiness::Throttled {
             trace!("Deferring receiving packets");
-            break;
+            return false;
         }
 
         match e1000
@@ -235,25 +296,24 @@ fn receive_packets(e1000: &mut E1000<LibvfioUserContext>, shared_buffer: &mut [u
             .receive(shared_buffer)
             .unwrap()
         {
-            Some(len) => {
-                if !e1000.receive_state.is_ready() {
-                    // Drop packet
+            Some(len) => match e1000.receive(&shared_buffer[..len]) {
+                Ok(ReceiveOutcome::Offline) => {
                     debug!(
-                        "Dropping {} incoming bytes, nic not ready to receive yet",
+                        "Dropped {} incoming bytes, nic not ready to receive yet",
                         len
                     );
-                    continue;
                 }
-                match e1000.receive(&shared_buffer[..len]) {
-                    Ok(_) => {}
-                    Err(err) => {
-                        warn!("Error handling receive event, skipping ({})", err);
-                    }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("Error handling receive event, skipping ({})", err);
                 }
-            }
+            },
             None => {
-                break;
+                return false;
             }
         }
     }
+
+    trace!("Receive budget used up, yielding");
+    true
 }
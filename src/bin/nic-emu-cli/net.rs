@@ -1,55 +1,206 @@
-use std::io::ErrorKind;
-use std::os::fd::{AsRawFd, RawFd};
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
 use std::process::Command;
 
+use anyhow::{anyhow, ensure, Context, Result};
 use ipnet::IpNet;
 use log::{debug, info, warn};
+use nic_emu::e1000::{ETHERNET_HEADER_LENGTH, VLAN_TAG_LENGTH};
 use tun_tap::{Iface, Mode};
 
+// Returned by TUNSETIFF if the interface already exists and is attached to another process
+const EBUSY: i32 = 16;
+
+enum Device {
+    Tap(Iface),
+    // Already opened tap interface, e.g. passed by a VMM
+    Fd(File),
+}
+
+/// Configuration of an interface by name, as done when setting up and cleaning up tap interfaces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterfaceCommand<'a> {
+    SetMtu { name: &'a str, mtu: usize },
+    SetLink { name: &'a str, up: bool },
+    AddAddress { name: &'a str, net: IpNet },
+    DeleteAddress { name: &'a str, net: IpNet },
+}
+
+impl InterfaceCommand<'_> {
+    /// Arguments of the equivalent ip command
+    pub fn ip_args(&self) -> Vec<String> {
+        let args = match *self {
+            InterfaceCommand::SetMtu { name, mtu } => format!("link set mtu {} dev {}", mtu, name),
+            InterfaceCommand::SetLink { name, up } => {
+                let state = if up { "up" } else { "down" };
+                format!("link set {} {}", state, name)
+            }
+            InterfaceCommand::AddAddress { name, net } => {
+                format!("address add {} dev {}", net, name)
+            }
+            InterfaceCommand::DeleteAddress { name, net } => {
+                format!("address del {} dev {}", net, name)
+            }
+        };
+        args.split(' ').map(str::to_string).collect()
+    }
+}
+
+impl fmt::Display for InterfaceCommand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ip {}", self.ip_args().join(" "))
+    }
+}
+
+/// Runs the commands setting up and cleaning up tap interfaces,
+/// can be replaced to record them or to use another mechanism like netlink
+pub trait CommandRunner {
+    fn run(&self, command: &InterfaceCommand) -> Result<()>;
+}
+
+/// Runs commands as ip processes, i.e. requires iproute2
+pub struct ProcessCommandRunner;
+
+impl CommandRunner for ProcessCommandRunner {
+    fn run(&self, command: &InterfaceCommand) -> Result<()> {
+        let mut process = Command::new("ip");
+        process.args(command.ip_args());
+        debug!("Running {:?}", process);
+        let status = process
+            .status()
+            .with_context(|| format!("Failed to run {:?}", process))?;
+        ensure!(status.success(), "{:?} failed with {}", process, status);
+        Ok(())
+    }
+}
+
 pub struct Interface {
-    interface: Iface,
+    device: Device,
+    mtu: Option<usize>,
+    net: Option<IpNet>, // Address added and link set up by us, undone when dropped
+    runner: Box<dyn CommandRunner>,
 }
 
 impl Interface {
-    pub fn initialize(non_blocking: bool, tap_name: &str, net: Option<IpNet>) -> Self {
-        let interface = Iface::without_packet_info(tap_name, Mode::Tap).unwrap();
+    pub fn initialize(
+        non_blocking: bool, tap_name: &str, net: Option<IpNet>, mtu: Option<usize>,
+        runner: Box<dyn CommandRunner>,
+    ) -> Result<Self> {
+        let interface = Iface::without_packet_info(tap_name, Mode::Tap).map_err(|err| {
+            if err.kind() == ErrorKind::PermissionDenied {
+                anyhow!(
+                    "Permission denied opening tap interface \"{}\", CAP_NET_ADMIN is required",
+                    tap_name
+                )
+            } else if err.raw_os_error() == Some(EBUSY) {
+                anyhow!(
+                    "Tap interface \"{}\" already exists and is in use by another process",
+                    tap_name
+                )
+            } else {
+                anyhow!(err).context(format!("Failed to open tap interface \"{}\"", tap_name))
+            }
+        })?;
 
         if non_blocking {
-            interface.set_non_blocking().unwrap();
+            interface
+                .set_non_blocking()
+                .context("Failed to set tap interface non-blocking")?;
         }
 
-        if let Some(ip_net) = net {
-            let ip_net = ip_net.to_string();
-            let mut cmd_ip_add = Command::new("ip");
-            cmd_ip_add.args(["address", "add", &ip_net, "dev", interface.name()]);
+        set_up(runner.as_ref(), interface.name(), net.as_ref(), mtu)?;
+
+        info!("Interface \"{}\" setup!", interface.name());
 
-            let mut cmd_ip_up = Command::new("ip");
-            cmd_ip_up.args(["link", "set", "up", interface.name()]);
+        Ok(Interface {
+            device: Device::Tap(interface),
+            mtu,
+            net,
+            runner,
+        })
+    }
 
-            debug!("Running {:?}", cmd_ip_add);
-            cmd_ip_add.spawn().unwrap().wait().unwrap();
+    /// Wrap an inherited tap interface file descriptor, which needs to be set up already,
+    /// takes ownership of fd
+    pub fn from_raw_fd(fd: RawFd, non_blocking: bool) -> Result<Self> {
+        // Standard streams are never a tap interface, but would be closed when dropped
+        ensure!(
+            fd > libc::STDERR_FILENO,
+            "Tap fd {} is a standard stream",
+            fd
+        );
 
-            debug!("Running {:?}", cmd_ip_up);
-            cmd_ip_up.spawn().unwrap().wait().unwrap();
-        } else {
-            warn!(
-                "No automatic interface setup was specified (via --net), \
-                 make sure link is up before attaching"
-            )
+        // Safety: Only reads and changes file status flags of the given fd
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            ensure!(
+                flags >= 0,
+                "Invalid tap fd {}: {}",
+                fd,
+                std::io::Error::last_os_error()
+            );
+            if non_blocking {
+                ensure!(
+                    libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) >= 0,
+                    "Failed to set tap fd {} non-blocking: {}",
+                    fd,
+                    std::io::Error::last_os_error()
+                );
+            }
         }
 
-        info!("Interface \"{}\" setup!", interface.name());
+        info!("Using tap interface with fd {}", fd);
+
+        Ok(Interface {
+            // Safety: Fd is valid as checked by fcntl and owned exclusively from now on
+            device: Device::Fd(unsafe { File::from_raw_fd(fd) }),
+            mtu: None,
+            net: None,
+            runner: Box::new(ProcessCommandRunner),
+        })
+    }
+
+    /// Reject sending frames exceeding the MTU, without changing the interface's MTU
+    pub fn with_mtu(mut self, mtu: Option<usize>) -> Self {
+        self.mtu = mtu;
+        self
+    }
 
-        Interface { interface }
+    // Largest frame that fits the MTU, allowing for a VLAN tag
+    fn max_frame_length(&self) -> Option<usize> {
+        self.mtu
+            .map(|mtu| mtu + ETHERNET_HEADER_LENGTH + VLAN_TAG_LENGTH)
     }
 
     pub fn send(&self, buffer: &[u8]) -> std::io::Result<usize> {
-        self.interface.send(buffer)
+        if let Some(max_frame_length) = self.max_frame_length() {
+            if buffer.len() > max_frame_length {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Frame of {}B exceeds MTU of {}B (maximum frame length {}B)",
+                        buffer.len(),
+                        self.mtu.unwrap(),
+                        max_frame_length
+                    ),
+                ));
+            }
+        }
+        match &self.device {
+            Device::Tap(interface) => interface.send(buffer),
+            Device::Fd(file) => (&*file).write(buffer),
+        }
     }
 
     pub fn receive(&self, buffer: &mut [u8]) -> std::io::Result<Option<usize>> {
         // Instead of returning WouldBlock error, return None
-        match self.interface.recv(buffer) {
+        let result = match &self.device {
+            Device::Tap(interface) => interface.recv(buffer),
+            Device::Fd(file) => (&*file).read(buffer),
+        };
+        match result {
             Ok(length) => Ok(Some(length)),
             Err(err) => {
                 if err.kind() == ErrorKind::WouldBlock {
@@ -62,8 +213,57 @@ impl Interface {
     }
 }
 
+// Set MTU, add address and set link up of an opened tap interface
+fn set_up(
+    runner: &dyn CommandRunner, name: &str, net: Option<&IpNet>, mtu: Option<usize>,
+) -> Result<()> {
+    if let Some(mtu) = mtu {
+        runner.run(&InterfaceCommand::SetMtu { name, mtu })?;
+    }
+
+    if let Some(&net) = net {
+        runner.run(&InterfaceCommand::AddAddress { name, net })?;
+        runner.run(&InterfaceCommand::SetLink { name, up: true })?;
+    } else {
+        warn!(
+            "No automatic interface setup was specified (via --net), \
+             make sure link is up before attaching"
+        )
+    }
+    Ok(())
+}
+
+// Tap interfaces created by us are removed once closed, but persistent ones that already existed
+// would keep the setup, so undo it, errors are only logged as this may run while unwinding
+impl Drop for Interface {
+    fn drop(&mut self) {
+        let (Device::Tap(interface), Some(net)) = (&self.device, self.net) else {
+            return;
+        };
+
+        let name = interface.name();
+        let commands = [
+            InterfaceCommand::SetLink { name, up: false },
+            InterfaceCommand::DeleteAddress { name, net },
+        ];
+
+        for command in &commands {
+            if let Err(err) = self.runner.run(command) {
+                warn!(
+                    "Failed to clean up interface \"{}\": {:#}",
+                    interface.name(),
+                    err
+                );
+            }
+        }
+    }
+}
+
 impl AsRawFd for Interface {
     fn as_raw_fd(&self) -> RawFd {
-        self.interface.as_raw_fd()
+        match &self.device {
+            Device::Tap(interface) => interface.as_raw_fd(),
+            Device::Fd(file) => file.as_raw_fd(),
+        }
     }
 }
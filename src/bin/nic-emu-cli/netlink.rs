@@ -0,0 +1,216 @@
+use std::cell::Cell;
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::net::IpAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use anyhow::{ensure, Context, Result};
+use ipnet::IpNet;
+use log::debug;
+
+use crate::net::{CommandRunner, InterfaceCommand};
+
+const NLMSG_HEADER_LENGTH: usize = 16;
+const RTA_HEADER_LENGTH: usize = 4;
+const IFINFOMSG_LENGTH: usize = 16;
+const IFADDRMSG_LENGTH: usize = 8;
+const RESPONSE_BUFFER_SIZE: usize = 4096;
+
+/// Configures interfaces with requests on a NETLINK_ROUTE socket instead of spawning ip
+pub struct NetlinkCommandRunner {
+    socket: OwnedFd,
+    sequence: Cell<u32>,
+}
+
+impl NetlinkCommandRunner {
+    pub fn new() -> Result<Self> {
+        // Safety: Plain socket creation, fd is checked before taking ownership
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to open netlink socket");
+        }
+
+        Ok(NetlinkCommandRunner {
+            // Safety: Fd was just created and is owned exclusively
+            socket: unsafe { OwnedFd::from_raw_fd(fd) },
+            sequence: Cell::new(0),
+        })
+    }
+
+    // Send request to the kernel and wait for its acknowledgement
+    fn request(&self, mut message: Vec<u8>) -> io::Result<()> {
+        let sequence = self.sequence.get().wrapping_add(1);
+        self.sequence.set(sequence);
+        message[8..12].copy_from_slice(&sequence.to_ne_bytes());
+
+        // Safety: Zeroed sockaddr_nl is valid, addressing the kernel
+        let mut kernel: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        kernel.nl_family = libc::AF_NETLINK as u16;
+        // Safety: Message and address are valid for their given lengths
+        let sent = unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+                &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Acknowledgements are error messages with error code 0,
+        // responses to earlier requests that were given up on are skipped
+        let mut buffer = [0u8; RESPONSE_BUFFER_SIZE];
+        loop {
+            // Safety: Buffer is valid for its length
+            let received = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if received < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let response = &buffer[..received as usize];
+            if response.len() < NLMSG_HEADER_LENGTH + 4 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Truncated netlink response",
+                ));
+            }
+
+            let message_type = u16::from_ne_bytes(response[4..6].try_into().unwrap());
+            let response_sequence = u32::from_ne_bytes(response[8..12].try_into().unwrap());
+            if message_type != libc::NLMSG_ERROR as u16 || response_sequence != sequence {
+                continue;
+            }
+            let error = i32::from_ne_bytes(response[16..20].try_into().unwrap());
+            return match error {
+                0 => Ok(()),
+                error => Err(io::Error::from_raw_os_error(-error)),
+            };
+        }
+    }
+}
+
+impl CommandRunner for NetlinkCommandRunner {
+    fn run(&self, command: &InterfaceCommand) -> Result<()> {
+        let message = match *command {
+            InterfaceCommand::SetMtu { name, mtu } => {
+                let mtu = u32::try_from(mtu).with_context(|| format!("Invalid MTU {}", mtu))?;
+                link_message(interface_index(name)?, None, Some(mtu))
+            }
+            InterfaceCommand::SetLink { name, up } => {
+                link_message(interface_index(name)?, Some(up), None)
+            }
+            InterfaceCommand::AddAddress { name, net } => {
+                address_message(interface_index(name)?, net, true)
+            }
+            InterfaceCommand::DeleteAddress { name, net } => {
+                address_message(interface_index(name)?, net, false)
+            }
+        };
+
+        debug!("Running {} over netlink", command);
+        self.request(message)
+            .with_context(|| format!("Netlink request for {} failed", command))
+    }
+}
+
+fn interface_index(name: &str) -> Result<u32> {
+    let c_name = CString::new(name)?;
+    // Safety: Name is a valid nul terminated string
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    ensure!(
+        index != 0,
+        "Interface \"{}\" not found: {}",
+        name,
+        io::Error::last_os_error()
+    );
+    Ok(index)
+}
+
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+// Request with header, fixed payload and attributes, sequence number is set when sent
+fn build_message(
+    message_type: u16, flags: i32, payload: &[u8], attributes: &[(u16, &[u8])],
+) -> Vec<u8> {
+    let flags = (libc::NLM_F_REQUEST | libc::NLM_F_ACK | flags) as u16;
+    let mut message = vec![0u8; NLMSG_HEADER_LENGTH];
+    message[4..6].copy_from_slice(&message_type.to_ne_bytes());
+    message[6..8].copy_from_slice(&flags.to_ne_bytes());
+    message.extend_from_slice(payload);
+    message.resize(align(message.len()), 0);
+
+    for (attribute_type, data) in attributes {
+        let length = (RTA_HEADER_LENGTH + data.len()) as u16;
+        message.extend_from_slice(&length.to_ne_bytes());
+        message.extend_from_slice(&attribute_type.to_ne_bytes());
+        message.extend_from_slice(data);
+        message.resize(align(message.len()), 0);
+    }
+
+    let length = message.len() as u32;
+    message[0..4].copy_from_slice(&length.to_ne_bytes());
+    message
+}
+
+// Change link state and/or MTU of an existing link, like ip link set
+fn link_message(index: u32, up: Option<bool>, mtu: Option<u32>) -> Vec<u8> {
+    let mut info = [0u8; IFINFOMSG_LENGTH];
+    info[0] = libc::AF_UNSPEC as u8;
+    info[4..8].copy_from_slice(&index.to_ne_bytes());
+    if let Some(up) = up {
+        let flags = if up { libc::IFF_UP as u32 } else { 0 };
+        info[8..12].copy_from_slice(&flags.to_ne_bytes());
+        info[12..16].copy_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+    }
+
+    let mtu = mtu.map(u32::to_ne_bytes);
+    let attributes: Vec<(u16, &[u8])> = mtu
+        .iter()
+        .map(|mtu| (libc::IFLA_MTU, mtu.as_slice()))
+        .collect();
+    build_message(libc::RTM_NEWLINK, 0, &info, &attributes)
+}
+
+// Add or delete address with prefix length of net, like ip address add/del
+fn address_message(index: u32, net: IpNet, add: bool) -> Vec<u8> {
+    let (family, address) = match net.addr() {
+        IpAddr::V4(address) => (libc::AF_INET, address.octets().to_vec()),
+        IpAddr::V6(address) => (libc::AF_INET6, address.octets().to_vec()),
+    };
+
+    let mut info = [0u8; IFADDRMSG_LENGTH];
+    info[0] = family as u8;
+    info[1] = net.prefix_len();
+    info[3] = libc::RT_SCOPE_UNIVERSE;
+    info[4..8].copy_from_slice(&index.to_ne_bytes());
+
+    let (message_type, flags) = if add {
+        (libc::RTM_NEWADDR, libc::NLM_F_CREATE | libc::NLM_F_EXCL)
+    } else {
+        (libc::RTM_DELADDR, 0)
+    };
+    let attributes = [
+        (libc::IFA_LOCAL, address.as_slice()),
+        (libc::IFA_ADDRESS, address.as_slice()),
+    ];
+    build_message(message_type, flags, &info, &attributes)
+}
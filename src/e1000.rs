@@ -1,29 +1,154 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use anyhow::{anyhow, ensure, Result};
-use log::{info, trace};
+use log::{debug, error, info, trace, warn};
 
 use crate::e1000::descriptors::*;
 use crate::e1000::eeprom::EepromInterface;
-use crate::e1000::interrupts::InterruptMitigation;
+use crate::e1000::interrupts::{InterruptMitigation, ReceiveDelay};
 use crate::e1000::phy::Phy;
 use crate::e1000::receive::ReceiveState;
-use crate::e1000::registers::Registers;
-use crate::NicContext;
+use crate::e1000::registers::{Register, Registers};
+use crate::e1000::transmit::TransmitDescriptorSequence;
+use crate::{Clock, MonotonicClock, NicContext};
 
 mod descriptors;
 mod eeprom;
+mod events;
+mod frame;
 mod interrupts;
 mod phy;
 mod receive;
 mod registers;
+mod rss;
+mod stats;
 mod transmit;
 
+pub use crate::e1000::eeprom::EepromType;
+pub use crate::e1000::events::E1000Events;
+pub use crate::e1000::frame::{FrameDirection, ETHERNET_HEADER_LENGTH, VLAN_TAG_LENGTH};
+pub use crate::e1000::receive::{ReceiveOutcome, RxReadiness};
+pub use crate::e1000::stats::Stats;
+
+pub const RX_QUEUE_COUNT: usize = 2;
+pub const TX_QUEUE_COUNT: usize = 2;
+const MAX_FUNCTION_NUMBER: u8 = 3; // Two bit FUNC_ID in STATUS
+const PCIE_DEVICE_CONTROL_RELAXED_ORDERING: u16 = 1 << 4;
+const PCIE_DEVICE_CONTROL_NO_SNOOP: u16 = 1 << 11;
+const STATUS_FD: u32 = 1 << 0;
+const STATUS_SPEED_SHIFT: u32 = 6;
+
+/// PCI power management state as set in PMCSR
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PowerState {
+    #[default]
+    D0,
+    D3Hot,
+}
+
+/// Origin of a device reset, determines which state survives it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetReason {
+    /// Driver set CTRL.RST
+    Software,
+    /// Function level reset, or transition from D3hot to D0
+    FunctionLevel,
+    /// PCI bus reset, or the embedder lost its connection to the guest
+    Bus,
+}
+
+/// Emulated controller, selects device specific registers and interrupt capabilities
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Model {
+    #[default]
+    I82540EM,
+    /// Adds MSI-X with vectors allocated by IVAR
+    I82574L,
+}
+
+impl Model {
+    pub fn from_device_id(device_id: u16) -> Option<Self> {
+        match device_id {
+            0x100E => Some(Model::I82540EM),
+            0x10D3 => Some(Model::I82574L),
+            _ => None,
+        }
+    }
+
+    pub fn device_id(&self) -> u16 {
+        match self {
+            Model::I82540EM => 0x100E,
+            Model::I82574L => 0x10D3,
+        }
+    }
+
+    /// Number of MSI-X vectors to expose in the MSI-X capability, 0 if not supported
+    pub fn msix_vectors(&self) -> u16 {
+        match self {
+            Model::I82540EM => 0,
+            Model::I82574L => 5,
+        }
+    }
+}
+
+/// DMA attributes the driver allowed in PCIe Device Control, an embedder's NicContext may use
+/// them to relax ordering or skip cache coherency of its DMA accesses
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DmaAttributes {
+    pub relaxed_ordering: bool,
+    pub no_snoop: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkSpeed {
+    Mbps10,
+    Mbps100,
+    Mbps1000,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Duplex {
+    Half,
+    Full,
+}
+
+/// Link parameters as reported to the driver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkStatus {
+    pub up: bool,
+    pub speed: LinkSpeed,
+    pub duplex: Duplex,
+}
+
+/// Custom receive policy, frames are dropped if it returns false
+pub type ReceiveFilter = Box<dyn FnMut(&[u8]) -> bool>;
+
+/// Observer for all received and sent frames, cannot drop frames
+pub type FrameMonitor = Box<dyn FnMut(FrameDirection, &[u8])>;
+
 pub struct E1000<C: NicContext> {
     // Configuration
     pub nic_ctx: C,
     enable_interrupt_mitigation: bool,
+    min_itr_interval: Option<Duration>, // Floor for guest programmed ITR interval
+    rx_filter: Option<ReceiveFilter>,
+    monitor: Option<FrameMonitor>,
+    clock: Box<dyn Clock>,
+    // Remembers writes to unimplemented registers to read them back, None if disabled
+    register_fallback: Option<HashMap<u32, u32>>,
+    check_stale_descriptors: bool, // Debugging aid, warn about descriptors with DD already set
+
+    model: Model,
+    function_number: u8, // PCI function of a port on multi-port cards, reported in STATUS
 
     // Status
     pub receive_state: ReceiveState,
+    power_state: PowerState,
+    msix_enabled: bool,
+    dma_attributes: DmaAttributes,
+    rx_buffer_prefetch: bool, // Buffers of hardware owned RX descriptors are already prepared
+    interrupt_line_asserted: bool, // INTx level, until all unmasked causes are cleared
 
     // E1000 internals
     regs: Registers,
@@ -32,28 +157,165 @@ pub struct E1000<C: NicContext> {
     phy: Phy,
 
     // Nic-emu internals
-    rx_ring: Option<DescriptorRing>,
-    tx_ring: Option<DescriptorRing>,
-    transmit_tcp_context: Option<TransmitDescriptorTcpContext>,
+    rx_rings: [Option<DescriptorRing>; RX_QUEUE_COUNT],
+    rx_buffer_size: Option<usize>, // RCTL buffer size in effect while RX rings are set up
+    next_rx_queue: usize,          // Round-robin queue selection if RSS is disabled
+    tx_rings: [Option<DescriptorRing>; TX_QUEUE_COUNT],
+    transmit_tcp_contexts: [Option<TransmitDescriptorTcpContext>; TX_QUEUE_COUNT],
+    // Frames whose descriptors without EOP used up the ring, continued by the next TDT write
+    pending_tx_sequences: [Option<TransmitDescriptorSequence>; TX_QUEUE_COUNT],
+    descriptor_scratch: Vec<u8>, // Descriptors batched for write-back, allocated at ring setup
     interrupt_mitigation: Option<InterruptMitigation>,
+    receive_delay: Option<ReceiveDelay>,
+    tx_paused_until: Option<Instant>, // Transmit paused by link partner
+    systime_base: Instant,            // Device time (SYSTIM) zero, time stamps are relative to it
+    last_rx_timestamp: Option<Instant>,
+    events: E1000Events,
+    stats: Stats,
 }
 
 impl<C: NicContext> E1000<C> {
     /// Create a new E1000 instance, if mitigate_interrupts is true
-    /// the provided nic_ctx must have a one-shot timer implementation calling e1000.timer_elapsed()
+    /// the provided nic_ctx must have a one-shot timer implementation calling e1000.timer_elapsed(),
+    /// alternatively the timer deadline can be taken from e1000.events()
     pub fn new(nic_ctx: C, mitigate_interrupts: bool) -> Self {
+        let clock = MonotonicClock;
         E1000 {
             nic_ctx,
             enable_interrupt_mitigation: mitigate_interrupts,
+            min_itr_interval: None,
+            rx_filter: None,
+            monitor: None,
+            register_fallback: None,
+            check_stale_descriptors: false,
+            systime_base: clock.now(),
+            clock: Box::new(clock),
+            model: Default::default(),
+            function_number: 0,
             receive_state: ReceiveState::Offline,
+            power_state: Default::default(),
+            msix_enabled: false,
+            dma_attributes: Default::default(),
+            rx_buffer_prefetch: false,
+            interrupt_line_asserted: false,
             regs: Default::default(),
             io_addr: 0,
             eeprom: Default::default(),
             phy: Default::default(),
-            rx_ring: None,
-            tx_ring: None,
-            transmit_tcp_context: None,
+            rx_rings: Default::default(),
+            rx_buffer_size: None,
+            next_rx_queue: 0,
+            tx_rings: Default::default(),
+            transmit_tcp_contexts: Default::default(),
+            pending_tx_sequences: Default::default(),
+            descriptor_scratch: Vec::new(),
             interrupt_mitigation: Default::default(),
+            receive_delay: Default::default(),
+            tx_paused_until: None,
+            last_rx_timestamp: None,
+            events: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    /// Install a filter consulted for every received frame before any hardware filtering,
+    /// persists across device resets
+    pub fn set_rx_filter(&mut self, filter: ReceiveFilter) {
+        self.rx_filter = Some(filter);
+    }
+
+    pub fn clear_rx_filter(&mut self) {
+        self.rx_filter = None;
+    }
+
+    /// Install a monitor called for every frame passed to receive, before any filtering,
+    /// and every frame sent, persists across device resets
+    pub fn set_monitor(&mut self, monitor: FrameMonitor) {
+        self.monitor = Some(monitor);
+    }
+
+    pub fn clear_monitor(&mut self) {
+        self.monitor = None;
+    }
+
+    /// Replace clock used for interrupt mitigation and delays, the one-shot timer and
+    /// deadlines in events are expected to follow the new clock
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Remember writes to registers that are not emulated and return them on reads,
+    /// by default they are dropped to make unimplemented registers noticeable
+    pub fn set_register_fallback(&mut self, enabled: bool) {
+        self.register_fallback = enabled.then(HashMap::new);
+    }
+
+    /// Warn about descriptors handed to hardware with DD still set, which hints at a driver
+    /// reusing descriptors it does not own, off by default as it costs a check per descriptor
+    pub fn set_stale_descriptor_check(&mut self, enabled: bool) {
+        self.check_stale_descriptors = enabled;
+    }
+
+    /// Report a port of a multi-port card in STATUS.FUNC_ID, has to match the PCI function,
+    /// ports share the eeprom, so drivers derive the ethernet address of odd functions
+    /// by flipping the lowest bit
+    pub fn set_function_number(&mut self, function_number: u8) -> Result<()> {
+        ensure!(
+            function_number <= MAX_FUNCTION_NUMBER,
+            "Function number {} exceeds maximum of {}",
+            function_number,
+            MAX_FUNCTION_NUMBER
+        );
+        self.function_number = function_number;
+        self.regs.status.FUNC_ID = function_number;
+        Ok(())
+    }
+
+    /// Select emulated controller, has to match the device id in PCI config space
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Replace the eeprom by an empty one of the given type, its contents have to be packed or
+    /// loaded afterwards, EECD reports the type after the next reset
+    pub fn set_eeprom_type(&mut self, eeprom_type: EepromType) {
+        self.eeprom = EepromInterface::new(eeprom_type);
+    }
+
+    /// To be called by embedders handling MSI-X capability writes in PCI config space,
+    /// interrupts are then signaled through trigger_interrupt_vector
+    pub fn set_msix_enabled(&mut self, enabled: bool) {
+        if enabled && self.model.msix_vectors() == 0 {
+            warn!("Ignoring MSI-X enable, not supported by {:?}", self.model);
+            return;
+        }
+        self.msix_enabled = enabled;
+    }
+
+    /// Currently effective MAC address as programmed in the receive address registers
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.regs.get_mac()
+    }
+
+    /// Link state as seen by the driver in STATUS, the link is only up if the PHY agrees
+    pub fn link_status(&self) -> LinkStatus {
+        let status = u32::from_le_bytes(self.regs.status.read().unwrap());
+        LinkStatus {
+            up: self.regs.status.LU && self.phy.status.link_status,
+            speed: match (status >> STATUS_SPEED_SHIFT) & 0b11 {
+                0b00 => LinkSpeed::Mbps10,
+                0b01 => LinkSpeed::Mbps100,
+                _ => LinkSpeed::Mbps1000,
+            },
+            duplex: if status & STATUS_FD != 0 {
+                Duplex::Full
+            } else {
+                Duplex::Half
+            },
         }
     }
 
@@ -75,6 +337,16 @@ impl<C: NicContext> E1000<C> {
                     if write { "write" } else { "read" },
                     offset
                 );
+
+                if let Some(fallback) = &mut self.register_fallback {
+                    let offset = offset as u32;
+                    if write {
+                        fallback.insert(offset, u32::from_le_bytes(data.try_into().unwrap()));
+                    } else {
+                        let value = fallback.get(&offset).copied().unwrap_or(0);
+                        data.copy_from_slice(&value.to_le_bytes());
+                    }
+                }
             }
         }
 
@@ -112,30 +384,99 @@ impl<C: NicContext> E1000<C> {
         }
     }
 
-    pub fn reset_e1000(&mut self) {
+    /// Reset device state, a software reset only resets the MAC, keeping the PHY with its link
+    /// state and PCI function state like power state and MSI-X enable,
+    /// function level and bus resets reset all of it as there is only a single function
+    pub fn reset_e1000(&mut self, reason: ResetReason) {
+        let link_up = self.regs.status.LU;
+
         self.receive_state = ReceiveState::Offline;
         self.regs = Default::default();
-        self.regs
-            .set_mac(self.eeprom.initial_eeprom.ethernet_address());
-        self.phy = Default::default();
+        self.regs.set_mac(self.eeprom.ethernet_address());
+        self.regs.status.FUNC_ID = self.function_number;
+        self.update_eecd_eeprom_type();
+
+        match reason {
+            ResetReason::Software => self.regs.status.LU = link_up,
+            ResetReason::FunctionLevel | ResetReason::Bus => {
+                self.phy = Default::default();
+                self.power_state = Default::default();
+                self.msix_enabled = false;
+                self.dma_attributes = Default::default();
+            }
+        }
 
         // Reset previous rx, tx values
-        self.rx_ring = None;
-        self.tx_ring = None;
-        self.transmit_tcp_context = None;
+        self.rx_rings = Default::default();
+        self.rx_buffer_size = None;
+        self.next_rx_queue = 0;
+        self.tx_rings = Default::default();
+        self.transmit_tcp_contexts = Default::default();
+        self.pending_tx_sequences = Default::default();
 
-        // Reset interrupt mitigation
-        self.nic_ctx.delete_timer();
+        // Reset interrupt mitigation and delays
+        self.delete_timer();
         self.interrupt_mitigation = None;
+        self.receive_delay = None;
+        self.tx_paused_until = None;
+        self.interrupt_line_asserted = false;
+
+        if let Some(fallback) = &mut self.register_fallback {
+            fallback.clear();
+        }
+
+        self.systime_base = self.clock.now();
+    }
+
+    /// To be called by embedders when the client detaches, guest addresses of its rings become
+    /// invalid, so the device is quiesced like by a bus reset: rings are dropped, pending timers
+    /// deleted and receiving stopped, leaving a clean device for the next client to initialize
+    pub fn on_detach(&mut self) {
+        info!("Quiescing device on client detach.");
+        self.reset_e1000(ResetReason::Bus);
+    }
+
+    /// To be called by embedders handling PMCSR writes in PCI config space,
+    /// a transition from D3hot to D0 resets the device
+    pub fn set_power_state(&mut self, power_state: PowerState) {
+        if self.power_state == PowerState::D3Hot && power_state == PowerState::D0 {
+            info!("Reset by transition from D3hot to D0.");
+            self.reset_e1000(ResetReason::FunctionLevel);
+        }
+        self.power_state = power_state;
+    }
+
+    /// To be called by embedders handling PCIe Device Control writes in PCI config space
+    pub fn set_pcie_device_control(&mut self, device_control: u16) {
+        if self.model == Model::I82540EM {
+            warn!(
+                "Ignoring PCIe Device Control, {:?} is a PCI device",
+                self.model
+            );
+            return;
+        }
+        self.dma_attributes = DmaAttributes {
+            relaxed_ordering: device_control & PCIE_DEVICE_CONTROL_RELAXED_ORDERING != 0,
+            no_snoop: device_control & PCIE_DEVICE_CONTROL_NO_SNOOP != 0,
+        };
+    }
+
+    pub fn dma_attributes(&self) -> DmaAttributes {
+        self.dma_attributes
     }
 
     fn ctrl_write(&mut self) {
         if self.regs.ctrl.RST {
             info!("Reset by driver.");
-            self.reset_e1000();
+            self.reset_e1000(ResetReason::Software);
             return;
         }
 
+        if self.regs.ctrl.PHY_RST {
+            info!("PHY reset by driver.");
+            self.phy_reset();
+        }
+
         if self.regs.ctrl.SLU {
             info!("Link up.");
             self.regs.status.LU = true;
@@ -145,29 +486,104 @@ impl<C: NicContext> E1000<C> {
     }
 
     fn rctl_write(&mut self) {
-        if self.regs.rctl.EN && self.rx_ring.is_none() {
-            self.setup_rx_ring();
+        self.check_rx_buffer_size();
+        if self.regs.rctl.EN {
+            // Only set up queues that have been programmed
+            for queue in 0..RX_QUEUE_COUNT {
+                if self.rx_rings[queue].is_none() && self.regs.rd[queue].len.length != 0 {
+                    self.setup_rx_ring(queue);
+                } else {
+                    // Buffer size or descriptor format may have changed
+                    self.prefetch_rx_buffers(queue, None);
+                }
+            }
         }
         self.update_receive_state();
     }
 
+    // Buffers of descriptors already owned by hardware were sized by the driver for the old
+    // BSIZE/BSEX, receiving with a larger size overruns them, frames are split by the new size
+    fn check_rx_buffer_size(&mut self) {
+        let buffer_size = self.regs.rctl.get_buffer_size();
+        let Some(old_buffer_size) = self.rx_buffer_size.replace(buffer_size) else {
+            return;
+        };
+        if old_buffer_size == buffer_size {
+            return;
+        }
+
+        let hw_descriptors: usize = self
+            .rx_rings
+            .iter()
+            .flatten()
+            .map(DescriptorRing::hardware_owned_descriptors)
+            .sum();
+        if buffer_size > old_buffer_size && hw_descriptors != 0 {
+            warn!(
+                "RX buffer size grew from {} to {} bytes with {} descriptors owned by hardware, \
+                their buffers may be overrun, RX rings should be reset when changing it",
+                old_buffer_size, buffer_size, hw_descriptors
+            );
+        } else {
+            debug!(
+                "RX buffer size changed from {} to {} bytes",
+                old_buffer_size, buffer_size
+            );
+        }
+    }
+
     fn tctl_write(&mut self) {
-        if self.regs.tctl.EN && self.tx_ring.is_none() {
-            self.setup_tx_ring();
+        if self.regs.tctl.EN {
+            // Only set up queues that have been programmed
+            for queue in 0..TX_QUEUE_COUNT {
+                if self.tx_rings[queue].is_none() && self.regs.td[queue].len.length != 0 {
+                    self.setup_tx_ring(queue);
+                }
+            }
         }
     }
 
-    fn rdt_write(&mut self) {
-        if let Some(rx_ring) = &mut self.rx_ring {
+    fn rdh_write(&mut self, queue: usize) {
+        if let Some(rx_ring) = &mut self.rx_rings[queue] {
+            if let Err(err) = rx_ring.update_head(self.regs.rd[queue].h.head as usize) {
+                error!("Ignoring RDH{} write: {}", queue, err);
+                self.regs.rd[queue].h.head = rx_ring.head as u16;
+                return;
+            }
+            self.prefetch_rx_buffers(queue, None);
+
+            // Free descriptors changed, may need to (un)throttle
+            self.update_receive_state();
+        }
+        // Else RDH was just initialized
+    }
+
+    fn tdh_write(&mut self, queue: usize) {
+        if let Some(tx_ring) = &mut self.tx_rings[queue] {
+            if let Err(err) = tx_ring.update_head(self.regs.td[queue].h.head as usize) {
+                error!("Ignoring TDH{} write: {}", queue, err);
+                self.regs.td[queue].h.head = tx_ring.head as u16;
+            }
+        }
+    }
+
+    fn rdt_write(&mut self, queue: usize) {
+        if let Some(rx_ring) = &mut self.rx_rings[queue] {
             // Software is done with the received packet(s)
-            rx_ring.tail = self.regs.rd_t.tail as usize;
+            let old_tail = rx_ring.tail;
+            if let Err(err) = rx_ring.update_tail(self.regs.rd[queue].t.tail as usize) {
+                error!("Ignoring RDT{} write: {}", queue, err);
+                return;
+            }
+            // Only the newly posted descriptors need their buffers prepared
+            self.prefetch_rx_buffers(queue, Some(old_tail));
 
             self.update_receive_state();
         }
         // Else RDT was just initialized
     }
 
-    fn tdt_write(&mut self) {
-        self.process_tx_ring();
+    fn tdt_write(&mut self, queue: usize) {
+        self.process_tx_queue(queue);
     }
 }
@@ -1,5 +1,5 @@
-use anyhow::{anyhow, Result};
-use log::{debug, error};
+use anyhow::{anyhow, ensure, Result};
+use log::{debug, error, warn};
 use packed_struct::derive::PackedStruct;
 use packed_struct::prelude::*;
 use packed_struct::{PackedStruct, PackingResult};
@@ -10,6 +10,12 @@ use crate::NicContext;
 // Each descriptor is 16 bytes long, 8 for buffer address, rest for status, length, etc...
 const DESCRIPTOR_LENGTH: usize = 16;
 
+// Maximum number of adjacent descriptors written back in a single DMA write
+const WRITE_BACK_BATCH: usize = 8;
+
+// Rings are at most 64KiB long, even though the length register allows for larger values
+const MAX_RING_DESCRIPTORS: usize = 4096;
+
 #[derive(Debug)]
 pub struct DescriptorRing {
     ring_address: usize,
@@ -18,7 +24,26 @@ pub struct DescriptorRing {
     pub tail: usize, // Updated by client
 }
 
+// Single descriptors are transferred through a fixed size buffer on the stack, batched
+// write-backs through the device's scratch buffer, so neither allocates per descriptor
 impl DescriptorRing {
+    // Head and tail registers may have been programmed before the length,
+    // indices outside the ring are reset to its start like an empty ring
+    fn new(ring_address: usize, length: usize, head: usize, tail: usize, name: &str) -> Self {
+        let mut ring = DescriptorRing {
+            ring_address,
+            length,
+            head: 0,
+            tail: 0,
+        };
+        if let Err(err) = ring.update_head(head).and_then(|_| ring.update_tail(tail)) {
+            warn!("Resetting head and tail of {} ring: {}", name, err);
+            ring.head = 0;
+            ring.tail = 0;
+        }
+        ring
+    }
+
     fn read_descriptor<T>(&self, index: usize, nic_ctx: &mut dyn NicContext) -> Result<T>
     where
         T: PackedStruct<ByteArray = [u8; DESCRIPTOR_LENGTH]>,
@@ -51,6 +76,28 @@ impl DescriptorRing {
         Ok(())
     }
 
+    // Descriptor bytes as found in memory, for logging malformed descriptors
+    pub fn read_raw(&self, index: usize, nic_ctx: &mut dyn NicContext) -> [u8; DESCRIPTOR_LENGTH] {
+        nic_ctx.dma_prepare(self.ring_address, self.length * DESCRIPTOR_LENGTH);
+
+        let mut data = [0u8; DESCRIPTOR_LENGTH];
+        nic_ctx.dma_read(
+            self.ring_address,
+            data.as_mut_slice(),
+            index * DESCRIPTOR_LENGTH,
+        );
+        data
+    }
+
+    // Compared as u128, rings may end beyond the top of the address space
+    fn overlaps(&self, other: &DescriptorRing) -> bool {
+        let end = self.ring_address as u128 + (self.length * DESCRIPTOR_LENGTH) as u128;
+        let other_end = other.ring_address as u128 + (other.length * DESCRIPTOR_LENGTH) as u128;
+        (self.ring_address as u128) < other_end && (other.ring_address as u128) < end
+    }
+
+    // Head equal to tail means empty, so at most capacity() descriptors are owned by hardware,
+    // e.g. with head 0 and tail length - 1, or tail right behind head after wrapping around
     pub fn hardware_owned_descriptors(&self) -> usize {
         let mut tail = self.tail;
         if tail < self.head {
@@ -60,13 +107,50 @@ impl DescriptorRing {
         tail - self.head
     }
 
+    pub fn descriptor_count(&self) -> usize {
+        self.length
+    }
+
+    // One descriptor always stays unused, otherwise a full ring would look empty
+    pub fn capacity(&self) -> usize {
+        self.length - 1
+    }
+
+    // Tail has to stay inside the ring, otherwise head would never reach it when wrapping around
+    pub fn update_tail(&mut self, tail: usize) -> Result<()> {
+        ensure!(
+            tail < self.length,
+            "Tail {} out of bounds for ring with {} descriptors",
+            tail,
+            self.length
+        );
+        self.tail = tail;
+        Ok(())
+    }
+
+    // Head is normally only advanced by hardware, but drivers reset it to 0 when reinitializing
+    pub fn update_head(&mut self, head: usize) -> Result<()> {
+        ensure!(
+            head < self.length,
+            "Head {} out of bounds for ring with {} descriptors",
+            head,
+            self.length
+        );
+        self.head = head;
+        Ok(())
+    }
+
     // Is the section owned by hardware empty?
     pub fn is_empty(&self) -> bool {
         self.head == self.tail
     }
 
+    pub fn next_index(&self, index: usize) -> usize {
+        (index + 1) % self.length
+    }
+
     pub fn advance_head(&mut self) {
-        self.head = (self.head + 1) % self.length;
+        self.head = self.next_index(self.head);
     }
 
     fn head_check(&self) -> Result<()> {
@@ -87,6 +171,66 @@ impl DescriptorRing {
         self.read_descriptor(self.head, nic_ctx)
     }
 
+    /// Read any descriptor regardless of ownership, e.g. to inspect already written back ones
+    pub fn read_at<T>(&self, index: usize, nic_ctx: &mut dyn NicContext) -> Result<T>
+    where
+        T: PackedStruct<ByteArray = [u8; DESCRIPTOR_LENGTH]>,
+    {
+        ensure!(
+            index < self.length,
+            "Index {} out of bounds for ring with {} descriptors",
+            index,
+            self.length
+        );
+        self.read_descriptor(index, nic_ctx)
+    }
+
+    /// Queue write-back of head descriptor and advance head,
+    /// queued descriptors are flushed first if the head is not adjacent to them
+    pub fn queue_write_back_and_advance_head<T>(
+        &mut self, desc: &T, write_back: &mut DescriptorWriteBack, nic_ctx: &mut dyn NicContext,
+    ) -> Result<()>
+    where
+        T: PackedStruct<ByteArray = [u8; DESCRIPTOR_LENGTH]>,
+    {
+        self.head_check()?;
+
+        if write_back.count == WRITE_BACK_BATCH
+            || (write_back.count > 0 && write_back.start + write_back.count != self.head)
+        {
+            self.flush_write_back(write_back, nic_ctx);
+        }
+        if write_back.count == 0 {
+            write_back.start = self.head;
+        }
+
+        let mut data = desc.pack()?;
+        data.reverse(); // Reverse because of endianness
+        let offset = write_back.count * DESCRIPTOR_LENGTH;
+        write_back.data[offset..offset + DESCRIPTOR_LENGTH].copy_from_slice(&data);
+        write_back.count += 1;
+
+        self.advance_head();
+        Ok(())
+    }
+
+    /// Write back all queued descriptors, has to happen before reporting them to the driver
+    pub fn flush_write_back(
+        &mut self, write_back: &mut DescriptorWriteBack, nic_ctx: &mut dyn NicContext,
+    ) {
+        if write_back.count == 0 {
+            return;
+        }
+
+        nic_ctx.dma_prepare(self.ring_address, self.length * DESCRIPTOR_LENGTH);
+        nic_ctx.dma_write(
+            self.ring_address,
+            &write_back.data[..write_back.count * DESCRIPTOR_LENGTH],
+            write_back.start * DESCRIPTOR_LENGTH,
+        );
+        write_back.count = 0;
+    }
+
     pub fn write_and_advance_head<T>(
         &mut self, desc: &T, nic_ctx: &mut dyn NicContext,
     ) -> Result<()>
@@ -100,25 +244,250 @@ impl DescriptorRing {
     }
 }
 
+/// Log a malformed transmit descriptor with its ring position, decoded type and raw content
+pub fn log_bad_descriptor(
+    queue: usize, index: usize, raw: &[u8; DESCRIPTOR_LENGTH], err: &anyhow::Error,
+) {
+    let mut reversed = *raw;
+    reversed.reverse(); // Reverse because of endianness
+    let descriptor_type = match TransmitDescriptorCommon::unpack(&reversed) {
+        Ok(common) => match (common.cmd_dext, common.dtyp) {
+            (false, _) => "legacy".to_string(),
+            (true, 0) => "context".to_string(),
+            (true, 1) => "data".to_string(),
+            (true, dtyp) => format!("unknown DTYP {}", dtyp),
+        },
+        Err(_) => "undecodable".to_string(),
+    };
+
+    error!(
+        "Bad TX{} descriptor at index {} ({}): {}, raw={:02x?}",
+        queue, index, descriptor_type, err, raw
+    );
+}
+
+// Packed descriptors waiting to be written back to adjacent ring entries,
+// kept in a scratch buffer of WRITE_BACK_BATCH descriptors
+pub struct DescriptorWriteBack<'a> {
+    start: usize,
+    count: usize,
+    data: &'a mut [u8],
+}
+
+impl<'a> DescriptorWriteBack<'a> {
+    pub fn new(scratch: &'a mut [u8]) -> Self {
+        assert_eq!(scratch.len(), DESCRIPTOR_LENGTH * WRITE_BACK_BATCH);
+        DescriptorWriteBack {
+            start: 0,
+            count: 0,
+            data: scratch,
+        }
+    }
+}
+
+// Drivers clear DD before handing descriptors to hardware, a set one hints at driver and
+// device disagreeing about ownership, hardware processes the descriptor regardless
+pub fn warn_stale_descriptor(direction: &str, queue: usize, index: usize) {
+    warn!(
+        "{} descriptor {} of ring {} already has DD set, possible driver/device desync",
+        direction, index, queue
+    );
+}
+
+fn checked_ring_length(length: usize, direction: &str, queue: usize) -> usize {
+    if length > MAX_RING_DESCRIPTORS {
+        warn!(
+            "Rejecting {} ring {} with {} descriptors, maximum is {}",
+            direction, queue, length, MAX_RING_DESCRIPTORS
+        );
+        return 0;
+    }
+    length
+}
+
 impl<C: NicContext> E1000<C> {
-    pub fn setup_rx_ring(&mut self) {
-        debug!("Initializing RX ring.");
-        self.rx_ring = Some(DescriptorRing {
-            ring_address: self.regs.get_receive_descriptor_base_address() as usize,
-            length: self.regs.rd_len.length as usize * 8,
-            head: self.regs.rd_h.head as usize,
-            tail: self.regs.rd_t.tail as usize,
-        });
-    }
-
-    pub fn setup_tx_ring(&mut self) {
-        debug!("Initializing TX ring.");
-        self.tx_ring = Some(DescriptorRing {
-            ring_address: self.regs.get_transmit_descriptor_base_address() as usize,
-            length: self.regs.td_len.length as usize * 8,
-            head: self.regs.td_h.head as usize,
-            tail: self.regs.td_t.tail as usize,
-        });
+    /// Head of RX ring as advanced by the device, None if the ring is not set up
+    pub fn rx_head(&self, queue: usize) -> Option<usize> {
+        self.rx_rings
+            .get(queue)?
+            .as_ref()
+            .map(|rx_ring| rx_ring.head)
+    }
+
+    /// Tail of RX ring as last written by the guest, None if the ring is not set up
+    pub fn rx_tail(&self, queue: usize) -> Option<usize> {
+        self.rx_rings
+            .get(queue)?
+            .as_ref()
+            .map(|rx_ring| rx_ring.tail)
+    }
+
+    /// Head of TX ring as advanced by the device, None if the ring is not set up
+    pub fn tx_head(&self, queue: usize) -> Option<usize> {
+        self.tx_rings
+            .get(queue)?
+            .as_ref()
+            .map(|tx_ring| tx_ring.head)
+    }
+
+    /// Tail of TX ring as last written by the guest, None if the ring is not set up
+    pub fn tx_tail(&self, queue: usize) -> Option<usize> {
+        self.tx_rings
+            .get(queue)?
+            .as_ref()
+            .map(|tx_ring| tx_ring.tail)
+    }
+
+    /// Whether the descriptor at index of TX ring was written back with DD set,
+    /// None if the ring is not set up or the descriptor can't be decoded
+    pub fn tx_descriptor_done(&mut self, queue: usize, index: usize) -> Option<bool> {
+        let tx_ring = self.tx_rings.get(queue)?.as_ref()?;
+        let descriptor: TransmitDescriptor = tx_ring.read_at(index, &mut self.nic_ctx).ok()?;
+        Some(descriptor.common.status_dd)
+    }
+
+    // Ring address and length in descriptors as currently programmed in the registers,
+    // length is zero if the programmed length exceeds the maximum
+    fn rx_ring_geometry(&self, queue: usize) -> (usize, usize) {
+        let rd = &self.regs.rd[queue];
+        let length = checked_ring_length(rd.len.length as usize * 8, "RX", queue);
+        (rd.get_base_address() as usize, length)
+    }
+
+    fn tx_ring_geometry(&self, queue: usize) -> (usize, usize) {
+        let td = &self.regs.td[queue];
+        let length = checked_ring_length(td.len.length as usize * 8, "TX", queue);
+        (td.get_base_address() as usize, length)
+    }
+
+    pub fn setup_rx_ring(&mut self, queue: usize) {
+        debug!("Initializing RX ring {}.", queue);
+        let (ring_address, length) = self.rx_ring_geometry(queue);
+        if length == 0 {
+            self.rx_rings[queue] = None;
+            return;
+        }
+        let ring = DescriptorRing::new(
+            ring_address,
+            length,
+            self.regs.rd[queue].h.head as usize,
+            self.regs.rd[queue].t.tail as usize,
+            &format!("RX{}", queue),
+        );
+        self.regs.rd[queue].h.head = ring.head as u16;
+        self.regs.rd[queue].t.tail = ring.tail as u16;
+        self.rx_rings[queue] = Some(ring);
+        self.rx_buffer_size = Some(self.regs.rctl.get_buffer_size());
+        self.check_ring_overlap();
+        self.prefetch_rx_buffers(queue, None);
+    }
+
+    pub fn setup_tx_ring(&mut self, queue: usize) {
+        debug!("Initializing TX ring {}.", queue);
+        if self.pending_tx_sequences[queue].take().is_some() {
+            warn!(
+                "Dropping incomplete frame of TX{}, ring is set up again",
+                queue
+            );
+        }
+        let (ring_address, length) = self.tx_ring_geometry(queue);
+        if length == 0 {
+            self.tx_rings[queue] = None;
+            return;
+        }
+        let ring = DescriptorRing::new(
+            ring_address,
+            length,
+            self.regs.td[queue].h.head as usize,
+            self.regs.td[queue].t.tail as usize,
+            &format!("TX{}", queue),
+        );
+        self.regs.td[queue].h.head = ring.head as u16;
+        self.regs.td[queue].t.tail = ring.tail as u16;
+        self.tx_rings[queue] = Some(ring);
+        self.descriptor_scratch
+            .resize(DESCRIPTOR_LENGTH * WRITE_BACK_BATCH, 0);
+        self.check_ring_overlap();
+    }
+
+    // Overlapping rings corrupt each others descriptors, which is almost certainly a driver bug,
+    // still proceed since it is the guest's memory after all
+    fn check_ring_overlap(&self) {
+        let rings = self
+            .rx_rings
+            .iter()
+            .enumerate()
+            .map(|(queue, ring)| ("RX", queue, ring))
+            .chain(
+                self.tx_rings
+                    .iter()
+                    .enumerate()
+                    .map(|(queue, ring)| ("TX", queue, ring)),
+            )
+            .filter_map(|(direction, queue, ring)| Some((direction, queue, ring.as_ref()?)))
+            .collect::<Vec<_>>();
+
+        for (i, (direction, queue, ring)) in rings.iter().enumerate() {
+            for (other_direction, other_queue, other_ring) in &rings[i + 1..] {
+                if ring.overlaps(other_ring) {
+                    warn!(
+                        "{} ring {} at {:x} overlaps {} ring {} at {:x}, descriptors will be corrupted",
+                        direction,
+                        queue,
+                        ring.ring_address,
+                        other_direction,
+                        other_queue,
+                        other_ring.ring_address
+                    );
+                }
+            }
+        }
+    }
+
+    // Base address or length registers were written, rings that already exist would otherwise
+    // keep operating on their old geometry, so rebuild them
+    pub fn rx_ring_geometry_write(&mut self, queue: usize) {
+        let (ring_address, length) = self.rx_ring_geometry(queue);
+        match &self.rx_rings[queue] {
+            Some(rx_ring) => {
+                if rx_ring.ring_address != ring_address || rx_ring.length != length {
+                    warn!(
+                        "RX ring {} changed while set up, rebuilding at {:x} with {} descriptors",
+                        queue, ring_address, length
+                    );
+                    self.setup_rx_ring(queue);
+                    self.update_receive_state();
+                }
+            }
+            None => {
+                // Queue was programmed after receive was already enabled
+                if self.regs.rctl.EN && length != 0 {
+                    self.setup_rx_ring(queue);
+                    self.update_receive_state();
+                }
+            }
+        }
+    }
+
+    pub fn tx_ring_geometry_write(&mut self, queue: usize) {
+        let (ring_address, length) = self.tx_ring_geometry(queue);
+        match &self.tx_rings[queue] {
+            Some(tx_ring) => {
+                if tx_ring.ring_address != ring_address || tx_ring.length != length {
+                    warn!(
+                        "TX ring {} changed while set up, rebuilding at {:x} with {} descriptors",
+                        queue, ring_address, length
+                    );
+                    self.setup_tx_ring(queue);
+                }
+            }
+            None => {
+                // Queue was programmed after transmit was already enabled
+                if self.regs.tctl.EN && length != 0 {
+                    self.setup_tx_ring(queue);
+                }
+            }
+        }
     }
 }
 
@@ -140,13 +509,106 @@ pub struct ReceiveDescriptor {
 
     #[packed_field(bits = "98")]
     status_ixsm: ReservedOne<packed_bits::Bits<1>>, // Ignore checksum indication, always on
+
+    #[packed_field(bits = "99")]
+    pub status_vp: bool, // VLAN tag stripped, its control information is in special
+
+    #[packed_field(bits = "112:127")]
+    pub special: u16,
+}
+
+// Header split receive descriptor, read format
+#[derive(PackedStruct, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "16", endian = "msb")]
+pub struct ReceiveDescriptorSplitRead {
+    #[packed_field(bits = "0:63")]
+    pub packet_buffer: u64,
+
+    #[packed_field(bits = "64:127")]
+    pub header_buffer: u64,
+}
+
+// Extended receive descriptor, read format
+#[derive(PackedStruct, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "16", endian = "msb")]
+pub struct ReceiveDescriptorExtendedRead {
+    #[packed_field(bits = "0:63")]
+    pub buffer: u64,
+
+    #[packed_field(bits = "64")]
+    pub status_dd: bool, // Overlaps DD of write-back format, cleared by driver
+}
+
+// Write-back format shared by extended and header split receive descriptors
+#[derive(PackedStruct, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "16", endian = "msb")]
+pub struct ReceiveDescriptorExtendedWriteBack {
+    #[packed_field(bits = "0:3")]
+    pub rss_type: u8, // Hash function used for rss_hash, 0 -> No hash computed
+
+    #[packed_field(bits = "4:16")]
+    pub packet_type: u16, // Recognized headers, not yet computed
+
+    #[packed_field(bits = "17:20")]
+    pub queue: u8, // Queue selected by RSS redirection table
+
+    // Header split only
+    #[packed_field(bits = "21:30")]
+    pub header_length: u16, // Bytes placed in header buffer
+
+    #[packed_field(bits = "31")]
+    pub split_header: bool, // Headers were split from payload
+
+    #[packed_field(bits = "32:63")]
+    pub rss_hash: u32,
+
+    // Extended status field offset 64 bits
+    #[packed_field(bits = "64")]
+    pub status_dd: bool, // Descriptor Done
+
+    #[packed_field(bits = "65")]
+    pub status_eop: bool, // End of packet
+
+    #[packed_field(bits = "66")]
+    pub status_ixsm: bool, // Ignore checksum indication
+
+    #[packed_field(bits = "67")]
+    pub status_vp: bool, // VLAN tag stripped, its control information is in vlan
+
+    #[packed_field(bits = "68")]
+    pub status_udpcs: bool, // UDP checksum calculated
+
+    #[packed_field(bits = "69")]
+    pub status_tcpcs: bool, // TCP checksum calculated
+
+    #[packed_field(bits = "70")]
+    pub status_ipcs: bool, // IPv4 checksum calculated
+
+    #[packed_field(bits = "73")]
+    pub status_ipidv: bool, // IP identification valid, set for IPv4 fragments
+
+    #[packed_field(bits = "80")]
+    pub status_tst: bool, // Time stamp taken, latched in RXSTMPL/RXSTMPH
+
+    // Extended errors field offset 84 bits
+    #[packed_field(bits = "93")]
+    pub error_tcpe: bool, // TCP/UDP checksum error
+
+    #[packed_field(bits = "94")]
+    pub error_ipe: bool, // IPv4 checksum error
+
+    #[packed_field(bits = "96:111")]
+    pub length: u16, // Bytes placed in (packet) buffer
+
+    #[packed_field(bits = "112:127")]
+    pub vlan: u16, // Tag control information of stripped VLAN tag
 }
 
 // Common transmit descriptor for differentiating between the different transmit descriptor types
 #[derive(PackedStruct, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "16", endian = "msb")]
 pub struct TransmitDescriptorCommon {
-    #[packed_field(bits = "84")]
+    #[packed_field(bits = "84:87")]
     dtyp: u8, // Extension type, 0000b -> TCP/IP context, 0001b -> TCP/IP data
 
     // Command field offset 88 bits in all transmit descriptor variants
@@ -154,7 +616,7 @@ pub struct TransmitDescriptorCommon {
     cmd_rs: bool, // Report Status, if set status_dd should be set after processing packet
 
     #[packed_field(bits = "92")] // Reserved in context descriptor
-    cmd_rps: bool, // Report Packet Sent, but treat just like Report Status
+    cmd_rps: bool, // Report Packet Sent, status_dd should be set after sending packet
 
     #[packed_field(bits = "93")]
     cmd_dext: bool, // Extension, 0 -> Legacy descriptor, 1 -> TCP/IP context or data descriptor
@@ -166,7 +628,11 @@ pub struct TransmitDescriptorCommon {
 
 impl TransmitDescriptorCommon {
     pub fn report_status(&self) -> bool {
-        self.cmd_rs || self.cmd_rps
+        self.cmd_rs
+    }
+
+    pub fn report_packet_sent(&self) -> bool {
+        self.cmd_rps
     }
 }
 
@@ -190,11 +656,14 @@ pub struct TransmitDescriptorLegacy {
     #[packed_field(bits = "90")]
     pub cmd_ic: bool, // Insert Checksum
 
+    #[packed_field(bits = "94")]
+    pub cmd_vle: bool, // VLAN Packet Enable, insert special as VLAN tag
+
     #[packed_field(bits = "104:111")]
     pub css: u8, // Checksum Start Field
 
     #[packed_field(bits = "112:127")]
-    special: u16, // Unused but keep to not delete it when writing back descriptor
+    pub special: u16, // VLAN tag control information if VLE is set
 }
 
 // TCP/IP context transmit descriptor, does not contain any data by itself,
@@ -254,6 +723,9 @@ pub struct TransmitDescriptorTcpData {
     #[packed_field(bits = "88")]
     pub dcmd_eop: bool, // End of packet
 
+    #[packed_field(bits = "94")]
+    pub dcmd_vle: bool, // VLAN Packet Enable, insert special as VLAN tag
+
     // Packet Options field offset 104 bits
     #[packed_field(bits = "104")]
     pub popts_ixsm: bool, // Insert IP Checksum
@@ -262,7 +734,7 @@ pub struct TransmitDescriptorTcpData {
     pub popts_txsm: bool, // Insert TCP/UDP Checksum
 
     #[packed_field(bits = "112:127")]
-    special: u16, // Unused but keep to not delete it when writing back descriptor
+    pub special: u16, // VLAN tag control information if VLE is set
 }
 
 #[derive(Debug)]
@@ -311,10 +783,8 @@ impl PackedStruct for TransmitDescriptor {
             (true, 1) => {
                 TransmitDescriptorVariant::TcpData(TransmitDescriptorTcpData::unpack(&src)?)
             }
-            _ => {
-                error!("Failed to match transmit descriptor type.");
-                return Err(PackingError::InternalError);
-            }
+            // Reserved DTYP, caller skips the descriptor
+            _ => return Err(PackingError::InvalidValue),
         };
 
         Ok(Self { common, variant })
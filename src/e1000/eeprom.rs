@@ -1,6 +1,7 @@
 use std::num::Wrapping;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, ensure, Result};
+use log::{trace, warn};
 use packed_struct::derive::PackedStruct;
 use packed_struct::PackedStruct;
 
@@ -11,12 +12,52 @@ const MICROWIRE_OPCODE_READ: u8 = 0x6;
 // There is also write, erase, erase/write enable, erase/write disable opcodes
 // but they are not used by linux e1000 kernel driver, at least in normal operation
 
-// Properties of small Microwire eeprom, are different for big microwire and for SPI
-const OPCODE_BITS: u16 = 3;
-const ADDRESS_BITS: u16 = 6;
+const SPI_OPCODE_READ: u8 = 0x3;
+const SPI_OPCODE_READ_STATUS: u8 = 0x5;
+const SPI_OPCODE_A8: u8 = 0x8; // Ninth address bit for devices with 8 address bits
 
+// Checksum covers the first 64 words regardless of eeprom size
+const CHECKSUM_WORD: usize = 0x3F;
 const DESIRED_CHECKSUM: u16 = 0xBABA;
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EepromType {
+    /// 64 words Microwire
+    #[default]
+    MicrowireSmall,
+    /// 256 words Microwire
+    MicrowireLarge,
+    /// 256 words SPI with 8 address bits, addressing bytes instead of words
+    Spi,
+}
+
+impl EepromType {
+    fn opcode_bits(&self) -> u16 {
+        match self {
+            EepromType::MicrowireSmall | EepromType::MicrowireLarge => 3,
+            EepromType::Spi => 8,
+        }
+    }
+
+    fn address_bits(&self) -> u16 {
+        match self {
+            EepromType::MicrowireSmall => 6,
+            EepromType::MicrowireLarge | EepromType::Spi => 8,
+        }
+    }
+
+    fn words(&self) -> usize {
+        match self {
+            EepromType::MicrowireSmall => 64,
+            EepromType::MicrowireLarge | EepromType::Spi => 256,
+        }
+    }
+
+    fn is_spi(&self) -> bool {
+        *self == EepromType::Spi
+    }
+}
+
 #[derive(Debug)]
 struct EepromWires {
     clock_input: bool,
@@ -30,12 +71,14 @@ enum EepromOperationStage {
     WaitingOpcode { written_opcode: u8 },
     WaitingAddress { written_address: u16 },
     Reading { address: u16 },
+    ReadingStatus, // SPI only, status is always ready
 }
 
 #[derive(Debug)]
 pub struct EepromInterface {
     pub initial_eeprom: Eeprom,
-    data: [u16; 64],
+    eeprom_type: EepromType,
+    data: Vec<u16>,
 
     previous_chip_select: bool,
     previous_clock: bool,
@@ -48,9 +91,25 @@ pub struct EepromInterface {
 // https://github.com/qemu/qemu/blob/64d3be986f9e2379bc688bf1d0aca0557e0035ca/hw/net/e1000.c#L489
 // But use single position and split reading opcode, address and accessing data stages into enum
 impl EepromInterface {
+    pub fn new(eeprom_type: EepromType) -> Self {
+        EepromInterface {
+            data: vec![0u16; eeprom_type.words()],
+            eeprom_type,
+            initial_eeprom: Default::default(),
+            previous_chip_select: false,
+            previous_clock: false,
+            stage: EepromOperationStage::WaitingOpcode { written_opcode: 0 },
+            bit_index: 0,
+        }
+    }
+
+    pub fn eeprom_type(&self) -> EepromType {
+        self.eeprom_type
+    }
+
     fn process_wires(&mut self, wires: &mut EepromWires) -> Result<()> {
-        // Check Chip select
-        if !wires.chip_select {
+        // Check Chip select, which is active low for SPI
+        if wires.chip_select == self.eeprom_type.is_spi() {
             self.previous_chip_select = false;
             return Ok(());
         }
@@ -89,14 +148,20 @@ impl EepromInterface {
                 }
                 EepromOperationStage::Reading { address } => {
                     let total_bit_offset = address * 16 + self.bit_index;
-                    // Compute which word and which bit in that word to read, wrap around data
-                    let word_index = (total_bit_offset / 16) as usize % self.data.len();
+                    // Compute which word and which bit in that word to read
                     let bit_index = total_bit_offset % 16;
 
-                    let word = self.data[word_index];
+                    let mut word = self.read_word(total_bit_offset / 16);
+                    if self.eeprom_type.is_spi() {
+                        // SPI eeprom is byte addressed, so lower byte is shifted out first
+                        word = word.swap_bytes();
+                    }
                     let mask = 0x8000 >> bit_index;
                     wires.data_output = word & mask != 0;
                 }
+                EepromOperationStage::ReadingStatus => {
+                    wires.data_output = false;
+                }
             }
         } else {
             // High -> Low: Increment index, update stage accordingly
@@ -104,27 +169,38 @@ impl EepromInterface {
 
             match self.stage {
                 EepromOperationStage::WaitingOpcode { written_opcode } => {
-                    if self.bit_index == OPCODE_BITS {
-                        match written_opcode {
-                            MICROWIRE_OPCODE_READ => {
-                                self.stage =
-                                    EepromOperationStage::WaitingAddress { written_address: 0 }
+                    if self.bit_index == self.eeprom_type.opcode_bits() {
+                        self.stage = match (self.eeprom_type.is_spi(), written_opcode) {
+                            (false, MICROWIRE_OPCODE_READ) => {
+                                EepromOperationStage::WaitingAddress { written_address: 0 }
+                            }
+                            (true, op) if op & !SPI_OPCODE_A8 == SPI_OPCODE_READ => {
+                                // Start with A8 so it ends up above the shifted in address bits
+                                EepromOperationStage::WaitingAddress {
+                                    written_address: (op & SPI_OPCODE_A8 != 0) as u16,
+                                }
                             }
-                            op => {
+                            (true, SPI_OPCODE_READ_STATUS) => EepromOperationStage::ReadingStatus,
+                            (_, op) => {
                                 return Err(anyhow!(
-                                    "Unknown/Unimplemented microwire opcode {:x}",
+                                    "Unknown/Unimplemented {:?} eeprom opcode {:x}",
+                                    self.eeprom_type,
                                     op
                                 ))
                             }
-                        }
+                        };
                         self.bit_index = 0;
                     }
                 }
                 EepromOperationStage::WaitingAddress { written_address } => {
-                    if self.bit_index == ADDRESS_BITS {
-                        self.stage = EepromOperationStage::Reading {
-                            address: written_address,
+                    if self.bit_index == self.eeprom_type.address_bits() {
+                        // SPI addresses bytes, but data is stored in words
+                        let address = if self.eeprom_type.is_spi() {
+                            written_address / 2
+                        } else {
+                            written_address
                         };
+                        self.stage = EepromOperationStage::Reading { address };
 
                         self.bit_index = 0;
                     }
@@ -135,6 +211,11 @@ impl EepromInterface {
         Ok(())
     }
 
+    // Wrap around data, same as sequential reads past the end
+    fn read_word(&self, address: u16) -> u16 {
+        self.data[address as usize % self.data.len()]
+    }
+
     pub fn pack_initial_eeprom(&mut self) {
         let mut pack = self.initial_eeprom.pack().unwrap();
         pack.reverse();
@@ -152,20 +233,75 @@ impl EepromInterface {
         }
 
         // DESIRED_CHECKSUM = sum + checksum word -> checksum word = DESIRED_CHECKSUM - sum
-        self.data[self.data.len() - 1] = DESIRED_CHECKSUM.wrapping_sub(sum.0);
+        self.data[CHECKSUM_WORD] = DESIRED_CHECKSUM.wrapping_sub(sum.0);
+    }
+
+    /// Replace eeprom contents with a raw image of little endian words instead of packing
+    /// initial_eeprom, words not covered by a smaller image are left empty,
+    /// the checksum is not enforced, see verify_checksum
+    pub fn load_image(&mut self, image: &[u8]) -> Result<()> {
+        let minimum_length = (CHECKSUM_WORD + 1) * 2;
+        let maximum_length = self.data.len() * 2;
+        ensure!(
+            image.chunks_exact(2).remainder().is_empty()
+                && (minimum_length..=maximum_length).contains(&image.len()),
+            "Eeprom image of {}B needs to be a whole number of words between {}B and {}B",
+            image.len(),
+            minimum_length,
+            maximum_length
+        );
+
+        self.data.fill(0);
+        for (word, chunk) in self.data.iter_mut().zip(image.chunks_exact(2)) {
+            *word = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(())
+    }
+
+    /// Sum of words 00h - 3Fh, including the checksum word
+    pub fn checksum(&self) -> u16 {
+        self.data[..=CHECKSUM_WORD]
+            .iter()
+            .fold(0u16, |sum, word| sum.wrapping_add(*word))
+    }
+
+    /// Drivers refuse eeproms whose words 00h - 3Fh do not sum up to 0xBABA
+    pub fn verify_checksum(&self) -> bool {
+        self.checksum() == DESIRED_CHECKSUM
+    }
+
+    /// Update checksum word 3Fh to make the checksum valid for the current contents
+    pub fn recompute_checksum(&mut self) {
+        let sum = self.checksum().wrapping_sub(self.data[CHECKSUM_WORD]);
+        self.data[CHECKSUM_WORD] = DESIRED_CHECKSUM.wrapping_sub(sum);
+    }
+
+    /// Ethernet address as currently stored in words 00h - 02h
+    pub fn ethernet_address(&self) -> [u8; 6] {
+        let mut address = [0u8; 6];
+        for (chunk, word) in address.chunks_exact_mut(2).zip(&self.data) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        address
+    }
+
+    /// Overwrite ethernet address in current contents, keeping the checksum valid
+    pub fn set_ethernet_address(&mut self, ethernet_address: [u8; 6]) {
+        let checksum_valid = self.verify_checksum();
+        for (word, chunk) in self.data.iter_mut().zip(ethernet_address.chunks_exact(2)) {
+            *word = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+
+        // Don't fix up checksum of images that were already invalid
+        if checksum_valid {
+            self.recompute_checksum();
+        }
     }
 }
 
 impl Default for EepromInterface {
     fn default() -> Self {
-        EepromInterface {
-            data: [0u16; 64],
-            initial_eeprom: Default::default(),
-            previous_chip_select: false,
-            previous_clock: false,
-            stage: EepromOperationStage::WaitingOpcode { written_opcode: 0 },
-            bit_index: 0,
-        }
+        EepromInterface::new(Default::default())
     }
 }
 
@@ -177,8 +313,34 @@ impl<C: NicContext> E1000<C> {
             data_input: self.regs.eecd.DI,
             data_output: false,
         };
-        self.eeprom.process_wires(&mut wires).unwrap();
+        // Unsupported opcodes abort the operation, following bits are read as a new opcode
+        if let Err(err) = self.eeprom.process_wires(&mut wires) {
+            warn!("Ignoring EEPROM operation: {}", err);
+            self.eeprom.stage = EepromOperationStage::WaitingOpcode { written_opcode: 0 };
+            self.eeprom.bit_index = 0;
+        }
         self.regs.eecd.DO = wires.data_output;
+        self.update_eecd_eeprom_type();
+    }
+
+    // Reads the same words as bit-banged EECD accesses without touching their state,
+    // so drivers probing both methods can interleave them, even mid-sequence
+    pub fn eerd_write(&mut self) {
+        if self.regs.eerd.START {
+            let address = self.regs.eerd.address;
+            self.regs.eerd.data = self.eeprom.read_word(address as u16);
+            self.regs.eerd.START = false;
+            self.regs.eerd.DONE = true;
+            trace!("EERD read {:x} at {:x}", self.regs.eerd.data, address);
+        }
+    }
+
+    // Report eeprom type and size, as register writes would otherwise clear them
+    pub(crate) fn update_eecd_eeprom_type(&mut self) {
+        let eeprom_type = self.eeprom.eeprom_type();
+        self.regs.eecd.EE_SIZE = eeprom_type == EepromType::MicrowireLarge;
+        self.regs.eecd.EE_ADDR_BITS = eeprom_type == EepromType::MicrowireLarge;
+        self.regs.eecd.EE_TYPE = eeprom_type.is_spi();
     }
 }
 
@@ -0,0 +1,59 @@
+use std::time::Instant;
+
+use crate::e1000::E1000;
+use crate::NicContext;
+
+/// Conditions an embedder's event loop needs to react to,
+/// alternative to implementing the timer callbacks of NicContext
+#[derive(Debug, Default)]
+pub struct E1000Events {
+    /// Deadline of the one-shot timer, call timer_elapsed() once reached, None if not armed
+    pub timer: Option<Instant>,
+    /// Timer was set or deleted since events were last taken
+    pub timer_changed: bool,
+
+    /// An interrupt was triggered since events were last taken
+    pub interrupt_triggered: bool,
+
+    /// Receiving became possible again since events were last taken,
+    /// frames deferred while throttled or offline should now be delivered
+    pub receive_ready: bool,
+    /// Readiness as returned by rx_ready() changed since events were last taken
+    pub receive_state_changed: bool,
+}
+
+impl E1000Events {
+    fn arm_timer(&mut self, deadline: Instant) {
+        self.timer = Some(deadline);
+        self.timer_changed = true;
+    }
+
+    fn disarm_timer(&mut self) {
+        self.timer = None;
+        self.timer_changed = true;
+    }
+}
+
+impl<C: NicContext> E1000<C> {
+    pub fn events(&self) -> &E1000Events {
+        &self.events
+    }
+
+    /// Return current events and reset all change indicators, timer deadline is kept
+    pub fn take_events(&mut self) -> E1000Events {
+        let events = std::mem::take(&mut self.events);
+        self.events.timer = events.timer;
+        events
+    }
+
+    pub(crate) fn set_timer(&mut self, deadline: Instant) {
+        self.events.arm_timer(deadline);
+        self.nic_ctx
+            .set_timer(deadline.saturating_duration_since(self.clock.now()));
+    }
+
+    pub(crate) fn delete_timer(&mut self) {
+        self.events.disarm_timer();
+        self.nic_ctx.delete_timer();
+    }
+}
@@ -0,0 +1,289 @@
+// Parsing of received frame headers, as far as needed for receive offloads
+
+use std::fmt;
+
+use internet_checksum::Checksum;
+use log::{log_enabled, trace, Level};
+
+pub const ETHERNET_HEADER_LENGTH: usize = 14;
+const ETHERNET_ADDRESS_LENGTH: usize = 6;
+const ETHERTYPE_OFFSET: usize = 12;
+pub const VLAN_TAG_LENGTH: usize = 4;
+const VLAN_ID_MASK: u16 = 0xFFF;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+pub const ETHERTYPE_VLAN: u16 = 0x8100; // Default of VET register
+
+const IPV4_MIN_HEADER_LENGTH: usize = 20;
+const IPV4_TOTAL_LENGTH_OFFSET: usize = 2;
+const IPV4_FRAGMENT_OFFSET: usize = 6; // Flags and fragment offset
+const IPV4_FRAGMENT_MASK: u16 = 0x3FFF; // More fragments flag + fragment offset
+const IPV4_PROTOCOL_OFFSET: usize = 9;
+pub const IPV4_ADDRESSES_OFFSET: usize = 12;
+pub const IPV4_ADDRESSES_LENGTH: usize = 8;
+const IPV6_HEADER_LENGTH: usize = 40;
+const IPV6_PAYLOAD_LENGTH_OFFSET: usize = 4;
+const IPV6_NEXT_HEADER_OFFSET: usize = 6;
+pub const IPV6_ADDRESSES_OFFSET: usize = 8;
+pub const IPV6_ADDRESSES_LENGTH: usize = 32;
+
+pub const IP_PROTOCOL_TCP: u8 = 6;
+pub const IP_PROTOCOL_UDP: u8 = 17;
+
+const TCP_MIN_HEADER_LENGTH: usize = 20;
+const TCP_DATA_OFFSET_OFFSET: usize = 12;
+const UDP_HEADER_LENGTH: usize = 8;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+#[derive(Debug)]
+pub struct IpHeader {
+    pub version: IpVersion,
+    pub offset: usize,
+    pub header_length: usize,
+    pub protocol: u8,
+    /// End of IP payload as specified in header, excluding any ethernet padding
+    pub end: usize,
+    /// IPv4 fragment by more fragments flag or fragment offset,
+    /// IPv6 fragments are not recognized as extension headers are not parsed
+    pub fragment: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FrameHeaders {
+    /// Combined length of all recognized headers (Ethernet, IPv4/IPv6, TCP/UDP)
+    pub length: usize,
+
+    pub ip: Option<IpHeader>,
+    /// Offset of TCP/UDP header, protocol is given by ip header
+    pub l4_offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameDirection {
+    Rx,
+    Tx,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EthernetHeader {
+    pub destination: [u8; ETHERNET_ADDRESS_LENGTH],
+    pub source: [u8; ETHERNET_ADDRESS_LENGTH],
+    /// Outer ethertype, VLAN tags are not skipped
+    pub ethertype: u16,
+}
+
+impl fmt::Display for EthernetHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_address = |address: &[u8]| {
+            address
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>()
+                .join(":")
+        };
+        write!(
+            f,
+            "{} -> {}, ethertype {:#06x}",
+            format_address(&self.source),
+            format_address(&self.destination),
+            self.ethertype
+        )
+    }
+}
+
+fn read_u16(frame: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([frame[offset], frame[offset + 1]])
+}
+
+/// Ethernet header of frame, None if frame is too short
+pub fn parse_ethernet_header(frame: &[u8]) -> Option<EthernetHeader> {
+    if frame.len() < ETHERNET_HEADER_LENGTH {
+        return None;
+    }
+    Some(EthernetHeader {
+        destination: frame[..ETHERNET_ADDRESS_LENGTH].try_into().unwrap(),
+        source: frame[ETHERNET_ADDRESS_LENGTH..ETHERTYPE_OFFSET]
+            .try_into()
+            .unwrap(),
+        ethertype: read_u16(frame, ETHERTYPE_OFFSET),
+    })
+}
+
+/// Log decoded ethernet header, parsing is skipped unless trace level is enabled
+pub fn trace_frame(direction: FrameDirection, frame: &[u8]) {
+    if !log_enabled!(Level::Trace) {
+        return;
+    }
+    match parse_ethernet_header(frame) {
+        Some(header) => trace!("{:?} {}B frame: {}", direction, frame.len(), header),
+        None => trace!("{:?} {}B frame: truncated header", direction, frame.len()),
+    }
+}
+
+/// Destination address of frame, None if frame is too short
+pub fn get_destination_address(frame: &[u8]) -> Option<&[u8]> {
+    frame.get(..ETHERNET_ADDRESS_LENGTH)
+}
+
+/// Multicast and broadcast addresses have the group bit set
+pub fn is_multicast_address(address: &[u8]) -> bool {
+    address[0] & 1 != 0
+}
+
+/// VLAN ID of tagged frame, None if frame is not tagged
+pub fn get_vlan_id(frame: &[u8], vlan_ethertype: u16) -> Option<u16> {
+    if frame.len() < ETHERNET_HEADER_LENGTH + VLAN_TAG_LENGTH
+        || read_u16(frame, ETHERTYPE_OFFSET) != vlan_ethertype
+    {
+        return None;
+    }
+    Some(read_u16(frame, ETHERTYPE_OFFSET + 2) & VLAN_ID_MASK)
+}
+
+/// Insert VLAN tag after the ethernet addresses, frame has to contain at least the addresses
+pub fn insert_vlan_tag(frame: &mut Vec<u8>, vlan_ethertype: u16, tci: u16) {
+    let mut tag = [0u8; VLAN_TAG_LENGTH];
+    tag[..2].copy_from_slice(&vlan_ethertype.to_be_bytes());
+    tag[2..].copy_from_slice(&tci.to_be_bytes());
+    frame.splice(ETHERTYPE_OFFSET..ETHERTYPE_OFFSET, tag);
+}
+
+/// Remove VLAN tag of tagged frame, returning the untagged frame and tag control information,
+/// None if frame is not tagged
+pub fn strip_vlan_tag(frame: &[u8], vlan_ethertype: u16) -> Option<(Vec<u8>, u16)> {
+    get_vlan_id(frame, vlan_ethertype)?;
+    let tci = read_u16(frame, ETHERTYPE_OFFSET + 2);
+    let mut untagged = Vec::with_capacity(frame.len() - VLAN_TAG_LENGTH);
+    untagged.extend_from_slice(&frame[..ETHERTYPE_OFFSET]);
+    untagged.extend_from_slice(&frame[ETHERTYPE_OFFSET + VLAN_TAG_LENGTH..]);
+    Some((untagged, tci))
+}
+
+/// Parse headers up to the first unrecognized or truncated one,
+/// VLAN tags are recognized by the ethertype programmed in VET
+pub fn parse_headers(frame: &[u8], vlan_ethertype: u16) -> FrameHeaders {
+    let mut headers = FrameHeaders::default();
+    if frame.len() < ETHERNET_HEADER_LENGTH {
+        return headers;
+    }
+
+    // Ethernet, skipping over a single VLAN tag
+    let mut offset = ETHERTYPE_OFFSET;
+    let mut ethertype = read_u16(frame, offset);
+    if ethertype == vlan_ethertype && frame.len() >= ETHERNET_HEADER_LENGTH + VLAN_TAG_LENGTH {
+        offset += VLAN_TAG_LENGTH;
+        ethertype = read_u16(frame, offset);
+    }
+    offset += 2;
+    headers.length = offset;
+
+    // IP, IPv6 extension headers are not recognized
+    let ip = match ethertype {
+        ETHERTYPE_IPV4 if frame.len() >= offset + IPV4_MIN_HEADER_LENGTH => IpHeader {
+            version: IpVersion::V4,
+            offset,
+            header_length: (frame[offset] & 0xF) as usize * 4,
+            protocol: frame[offset + IPV4_PROTOCOL_OFFSET],
+            end: offset + read_u16(frame, offset + IPV4_TOTAL_LENGTH_OFFSET) as usize,
+            fragment: read_u16(frame, offset + IPV4_FRAGMENT_OFFSET) & IPV4_FRAGMENT_MASK != 0,
+        },
+        ETHERTYPE_IPV6 if frame.len() >= offset + IPV6_HEADER_LENGTH => IpHeader {
+            version: IpVersion::V6,
+            offset,
+            header_length: IPV6_HEADER_LENGTH,
+            protocol: frame[offset + IPV6_NEXT_HEADER_OFFSET],
+            end: offset
+                + IPV6_HEADER_LENGTH
+                + read_u16(frame, offset + IPV6_PAYLOAD_LENGTH_OFFSET) as usize,
+            fragment: false,
+        },
+        _ => return headers,
+    };
+    if ip.header_length < IPV4_MIN_HEADER_LENGTH
+        || ip.end < offset + ip.header_length
+        || ip.end > frame.len()
+    {
+        return headers;
+    }
+    offset += ip.header_length;
+    headers.length = offset;
+
+    // Fragments do not carry a complete TCP/UDP datagram
+    let fragmented = ip.fragment;
+    let protocol = ip.protocol;
+    let ip_end = ip.end;
+    headers.ip = Some(ip);
+    if fragmented {
+        return headers;
+    }
+
+    // TCP/UDP
+    let (l4_header_length, l4_min_header_length) = match protocol {
+        IP_PROTOCOL_TCP if ip_end >= offset + TCP_MIN_HEADER_LENGTH => (
+            (frame[offset + TCP_DATA_OFFSET_OFFSET] >> 4) as usize * 4,
+            TCP_MIN_HEADER_LENGTH,
+        ),
+        IP_PROTOCOL_UDP => (UDP_HEADER_LENGTH, UDP_HEADER_LENGTH),
+        _ => return headers,
+    };
+    if l4_header_length < l4_min_header_length || ip_end < offset + l4_header_length {
+        return headers;
+    }
+    headers.l4_offset = Some(offset);
+    headers.length = offset + l4_header_length;
+
+    headers
+}
+
+/// Validate IPv4 header checksum, None if there is no IPv4 header
+pub fn verify_ipv4_checksum(frame: &[u8], headers: &FrameHeaders) -> Option<bool> {
+    let ip = headers.ip.as_ref()?;
+    if ip.version != IpVersion::V4 {
+        return None;
+    }
+
+    // Checksum over header including checksum field is zero if valid
+    let mut checksum = Checksum::new();
+    checksum.add_bytes(&frame[ip.offset..ip.offset + ip.header_length]);
+    Some(checksum.checksum() == [0, 0])
+}
+
+/// Validate TCP/UDP checksum including pseudo header,
+/// None if there is no TCP/UDP header or UDP checksum is unused
+pub fn verify_l4_checksum(frame: &[u8], headers: &FrameHeaders) -> Option<bool> {
+    let ip = headers.ip.as_ref()?;
+    let l4_offset = headers.l4_offset?;
+    let segment = &frame[l4_offset..ip.end];
+
+    if ip.protocol == IP_PROTOCOL_UDP
+        && ip.version == IpVersion::V4
+        && read_u16(segment, UDP_CHECKSUM_OFFSET) == 0
+    {
+        return None;
+    }
+
+    let mut checksum = Checksum::new();
+    match ip.version {
+        IpVersion::V4 => {
+            let addresses = ip.offset + IPV4_ADDRESSES_OFFSET;
+            checksum.add_bytes(&frame[addresses..addresses + IPV4_ADDRESSES_LENGTH]);
+            checksum.add_bytes(&[0, ip.protocol]);
+            checksum.add_bytes(&(segment.len() as u16).to_be_bytes());
+        }
+        IpVersion::V6 => {
+            let addresses = ip.offset + IPV6_ADDRESSES_OFFSET;
+            checksum.add_bytes(&frame[addresses..addresses + IPV6_ADDRESSES_LENGTH]);
+            checksum.add_bytes(&(segment.len() as u32).to_be_bytes());
+            checksum.add_bytes(&[0, 0, 0, ip.protocol]);
+        }
+    }
+    checksum.add_bytes(segment);
+    Some(checksum.checksum() == [0, 0])
+}
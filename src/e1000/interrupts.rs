@@ -1,8 +1,9 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use log::{trace, warn};
 use packed_struct::PackedStruct;
 
+use crate::e1000::stats::CountingNicContext;
 use crate::e1000::E1000;
 use crate::NicContext;
 
@@ -16,43 +17,183 @@ impl InterruptMitigation {
     fn is_active_at(&self, time: Instant) -> bool {
         self.expiration > time
     }
+}
+
+// Receive interrupt delay, RXT0 is reported once either timer expires
+pub(crate) struct ReceiveDelay {
+    /// Packet timer (RDTR), restarted by every received packet
+    packet_expiration: Instant,
+    /// Absolute timer (RADV), started by the first packet, None if disabled
+    absolute_expiration: Option<Instant>,
+}
 
-    fn is_active(&self) -> bool {
-        self.is_active_at(Instant::now())
+impl ReceiveDelay {
+    fn expiration(&self) -> Instant {
+        match self.absolute_expiration {
+            Some(absolute_expiration) => absolute_expiration.min(self.packet_expiration),
+            None => self.packet_expiration,
+        }
     }
 }
 
 impl<C: NicContext> E1000<C> {
     pub fn timer_elapsed(&mut self) {
         trace!("Timer elapsed");
+        // One-shot timer is not armed anymore
+        self.events.timer = None;
+
         if !self.enable_interrupt_mitigation {
             warn!("Timer elapsed called, but interrupt mitigation is disabled");
         }
 
+        // Timer is shared by receive delay, interrupt mitigation and transmit pause,
+        // handle whichever expired
+        let now = self.clock.now();
+        if let Some(tx_paused_until) = self.tx_paused_until {
+            if tx_paused_until <= now {
+                self.resume_transmit();
+            }
+        }
+
+        if let Some(receive_delay) = &self.receive_delay {
+            if receive_delay.expiration() <= now {
+                self.receive_delay = None;
+                self.report_rxt0();
+            }
+        }
+
         if let Some(mitigation) = &self.interrupt_mitigation {
-            if !mitigation.interrupt_after {
-                warn!("Timer elapsed called, but timer was not supposed to be scheduled");
-                return;
+            if mitigation.interrupt_after && !mitigation.is_active_at(now) {
+                // Clear before interrupting, skips checks and potential timer update
+                self.interrupt_mitigation = None;
+                self.interrupt();
             }
+        }
 
-            if mitigation.is_active() {
-                warn!(
-                    "Timer elapsed called too early, mitigation still active for {:?}",
-                    mitigation
-                        .expiration
-                        .saturating_duration_since(Instant::now())
+        // Rearm for remaining deadline, or if called too early
+        self.update_timer();
+    }
+
+    /// Alternative to a one-shot timer calling timer_elapsed(), e.g. for simulators without an OS
+    /// timer, fires the timer if its deadline was reached and returns whether it fired,
+    /// now has to be taken from the device's clock which can be replaced using set_clock()
+    pub fn poll_timers(&mut self, now: Instant) -> bool {
+        match self.events.timer {
+            Some(deadline) if deadline <= now => {
+                self.timer_elapsed();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Enable or disable interrupt mitigation at runtime, enabling takes effect with the next
+    /// interrupt, disabling delivers deferred interrupts right away as the timer is not used anymore
+    pub fn set_interrupt_mitigation(&mut self, enabled: bool) {
+        self.enable_interrupt_mitigation = enabled;
+        if enabled {
+            return;
+        }
+
+        let mut deferred = false;
+        if let Some(mitigation) = self.interrupt_mitigation.take() {
+            deferred |= mitigation.interrupt_after;
+        }
+        if self.receive_delay.take().is_some() {
+            self.regs.interrupt_cause.RXT0 = true;
+            deferred = true;
+        }
+        self.update_timer();
+
+        if deferred {
+            trace!("Delivering deferred interrupt after disabling interrupt mitigation");
+            self.interrupt();
+        }
+    }
+
+    /// Enforce a minimum interval between interrupts, even if the guest programs a lower ITR
+    /// interval or disables throttling, only takes effect with interrupt mitigation enabled
+    pub fn set_min_itr_interval(&mut self, min_interval: Option<Duration>) {
+        self.min_itr_interval = min_interval;
+    }
+
+    // Arm one-shot timer for the earliest pending deadline, or delete it if there is none
+    pub(crate) fn update_timer(&mut self) {
+        let mitigation = self
+            .interrupt_mitigation
+            .as_ref()
+            .filter(|mitigation| mitigation.interrupt_after)
+            .map(|mitigation| mitigation.expiration);
+        let receive_delay = self.receive_delay.as_ref().map(ReceiveDelay::expiration);
+
+        let deadline = mitigation
+            .into_iter()
+            .chain(receive_delay)
+            .chain(self.tx_paused_until)
+            .min();
+        if deadline == self.events.timer {
+            return;
+        }
+
+        match deadline {
+            Some(deadline) => {
+                trace!(
+                    "Scheduling timer for in {:?}",
+                    deadline.saturating_duration_since(self.clock.now())
                 );
+                self.set_timer(deadline);
+            }
+            None => self.delete_timer(),
+        }
+    }
+
+    /// Report RXT0 for a received packet, delayed by RDTR and RADV if set
+    pub(crate) fn report_rxt0_delayed(&mut self) {
+        // Delays need the timer which is only available with interrupt mitigation
+        let packet_delay = match self.regs.rdtr.get_delay() {
+            Some(packet_delay) if self.enable_interrupt_mitigation => packet_delay,
+            _ => {
+                self.report_rxt0();
                 return;
             }
-        } else {
-            warn!("Timer elapsed called, but mitigation is already disabled");
-            return;
+        };
+
+        let now = self.clock.now();
+        let absolute_expiration = match &self.receive_delay {
+            Some(receive_delay) => receive_delay.absolute_expiration,
+            None => self.regs.radv.get_delay().map(|delay| now + delay),
+        };
+        self.receive_delay = Some(ReceiveDelay {
+            packet_expiration: now + packet_delay,
+            absolute_expiration,
+        });
+        self.update_timer();
+    }
+
+    pub(crate) fn rdtr_write(&mut self) {
+        // Flush Partial Descriptor Block: Report pending RXT0 immediately, bit self-clears
+        if self.regs.rdtr.FPD {
+            self.regs.rdtr.FPD = false;
+            if self.receive_delay.take().is_some() {
+                self.report_rxt0();
+                self.update_timer();
+            }
         }
+    }
 
-        // Clear before interrupting, skips checks and potential timer deletion,
-        // which wouldn't be needed anyway since timer should operate in one-shot mode
-        self.interrupt_mitigation = None;
-        self.interrupt();
+    /// Whether the INTx line is currently asserted, i.e. an interrupt was triggered and
+    /// not all of its unmasked causes were cleared yet, always false with MSI-X
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_line_asserted
+    }
+
+    // Deassert INTx line once no unmasked cause remains, after ICR clears or IMC writes
+    pub(crate) fn update_interrupt_line(&mut self) {
+        let cause = u32::from_ne_bytes(self.regs.interrupt_cause.pack().unwrap());
+        let mask = u32::from_ne_bytes(self.regs.interrupt_mask.pack().unwrap());
+        if cause & mask == 0 {
+            self.interrupt_line_asserted = false;
+        }
     }
 
     /// Trigger
@@ -69,16 +210,14 @@ impl<C: NicContext> E1000<C> {
 
         // Interrupt mitigation
         if let Some(mitigation) = &mut self.interrupt_mitigation {
-            let now = Instant::now();
+            let now = self.clock.now();
             if mitigation.is_active_at(now) {
                 trace!("Skipping interrupt, mitigation active");
 
                 // Schedule timer to assert interrupt after mitigation ends
                 if !mitigation.interrupt_after {
-                    let delay = mitigation.expiration - now;
-                    trace!("Scheduling timer for in {:?}", delay);
-                    self.nic_ctx.set_timer(delay);
                     mitigation.interrupt_after = true;
+                    self.update_timer();
                 }
                 return;
             }
@@ -86,26 +225,36 @@ impl<C: NicContext> E1000<C> {
             // Else this interrupt was triggered by other cause that was randomly called
             // at just the right time
             // (couldn't be called by timer since it clears self.interrupt_mitigation before call)
-            if mitigation.interrupt_after {
-                trace!("Interrupt mitigation expired before timer called, so deleting timer");
-                self.nic_ctx.delete_timer();
-            }
+            let interrupt_after = mitigation.interrupt_after;
             self.interrupt_mitigation = None;
+            if interrupt_after {
+                trace!("Interrupt mitigation expired before timer called, so updating timer");
+                self.update_timer();
+            }
         }
 
         trace!(
             "Triggering interrupt, set causes: {:?}",
             self.regs.interrupt_cause
         );
-        self.nic_ctx.trigger_interrupt();
+        if self.msix_enabled {
+            self.trigger_msix_vectors();
+        } else {
+            let mut nic_ctx = CountingNicContext::new(&mut self.nic_ctx, &mut self.stats);
+            nic_ctx.interrupt_causes(self.regs.interrupt_cause.bits());
+            nic_ctx.trigger_interrupt();
+            self.interrupt_line_asserted = true;
+        }
+        self.events.interrupt_triggered = true;
 
         // Re-arm interrupt throttling timer (if enabled)
         // This should not lead to an infinite loop, as this doesn't set timer yet
         if self.enable_interrupt_mitigation {
-            if let Some(duration) = self.regs.interrupt_throttling.get_itr_interval() {
+            let interval = self.regs.interrupt_throttling.get_itr_interval();
+            if let Some(duration) = interval.max(self.min_itr_interval) {
                 trace!("Mitigating interrupts for next {:?}", duration);
                 self.interrupt_mitigation = Some(InterruptMitigation {
-                    expiration: Instant::now() + duration,
+                    expiration: self.clock.now() + duration,
                     // No timer is needed until interrupts are reported during mitigation
                     interrupt_after: false,
                 })
@@ -113,6 +262,50 @@ impl<C: NicContext> E1000<C> {
         }
     }
 
+    // Route causes to the MSI-X vectors allocated in IVAR (82574), receive and transmit causes
+    // are reported as queue 0 causes, everything else as other cause,
+    // queue causes set in EIAC are cleared again once their vector was triggered
+    fn trigger_msix_vectors(&mut self) {
+        let causes = &mut self.regs.interrupt_cause;
+        causes.RXQ0 |= causes.RXT0 || causes.RXDMT0 || causes.RXO || causes.SRPD || causes.ACK;
+        causes.TXQ0 |= causes.TXDW || causes.TXQE || causes.TXD_LOW;
+        causes.OTHER |= causes.LSC || causes.RXSEQ || causes.MDAC || causes.PHYINT;
+
+        let ivar = &self.regs.ivar;
+        let routes = [
+            (causes.RXQ0, ivar.rxq0_valid, ivar.rxq0_vector),
+            (causes.RXQ1, ivar.rxq1_valid, ivar.rxq1_vector),
+            (causes.TXQ0, ivar.txq0_valid, ivar.txq0_vector),
+            (causes.TXQ1, ivar.txq1_valid, ivar.txq1_vector),
+            (causes.OTHER, ivar.other_valid, ivar.other_vector),
+        ];
+
+        let mut nic_ctx = CountingNicContext::new(&mut self.nic_ctx, &mut self.stats);
+        nic_ctx.interrupt_causes(causes.bits());
+        let mut triggered = 0u8; // Bitmask to trigger shared vectors only once
+        for (pending, valid, vector) in routes {
+            // IVAR fields can address more vectors than the MSI-X table has entries
+            if pending && valid && vector as u16 >= self.model.msix_vectors() {
+                warn!(
+                    "Skipping MSI-X vector {} allocated in IVAR, out of range",
+                    vector
+                );
+                continue;
+            }
+            if pending && valid && triggered & (1 << vector) == 0 {
+                triggered |= 1 << vector;
+                nic_ctx.trigger_interrupt_vector(vector as u16);
+            }
+        }
+
+        let eiac = &self.regs.eiac;
+        causes.RXQ0 &= !eiac.RXQ0;
+        causes.RXQ1 &= !eiac.RXQ1;
+        causes.TXQ0 &= !eiac.TXQ0;
+        causes.TXQ1 &= !eiac.TXQ1;
+        causes.OTHER &= !eiac.OTHER;
+    }
+
     /// Transmit Descriptor Written Back & Transmit Queue Empty
     /// (With the latter always being the case after the former in this behavioral model)
     pub(crate) fn report_txdw_and_txqe(&mut self) {
@@ -123,7 +316,8 @@ impl<C: NicContext> E1000<C> {
         self.interrupt();
     }
 
-    /// Transmit Queue Empty
+    /// Transmit Queue Empty, only after descriptors were processed, so tail writes to an
+    /// already empty queue do not raise it again
     pub(crate) fn report_txqe(&mut self) {
         trace!("Reporting: Transmit Queue Empty");
         self.regs.interrupt_cause.TXQE = true;
@@ -144,6 +338,13 @@ impl<C: NicContext> E1000<C> {
         self.interrupt();
     }
 
+    /// Receive Descriptor Minimum Threshold Reached
+    pub(crate) fn report_rxdmt0(&mut self) {
+        trace!("Reporting: Receive Descriptor Minimum Threshold Reached");
+        self.regs.interrupt_cause.RXDMT0 = true;
+        self.interrupt();
+    }
+
     /// MDI/O Access Complete
     pub(crate) fn report_mdac(&mut self) {
         trace!("Reporting: MDI/O Access Complete");
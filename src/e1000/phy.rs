@@ -17,6 +17,12 @@ pub struct Phy {
 }
 
 impl<C: NicContext> E1000<C> {
+    pub fn phy_reset(&mut self) {
+        self.phy = Default::default();
+        // Link comes back up right away, as there is no auto-negotiation to wait for
+        self.phy.status.link_status = self.regs.status.LU;
+    }
+
     pub fn mdic_write(&mut self) {
         let offset = self.regs.mdic.register_address;
         let mut data = self.regs.mdic.data.to_be_bytes();
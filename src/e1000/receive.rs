@@ -1,12 +1,17 @@
+use std::time::Instant;
+
 use anyhow::{Context, Result};
-use log::{debug, trace};
+use log::{debug, error, trace};
 
 use crate::e1000::descriptors::*;
-use crate::e1000::E1000;
+use crate::e1000::frame::*;
+use crate::e1000::registers::{ReceiveDescriptorFormat, Registers};
+use crate::e1000::rss::{compute_rss, RssResult};
+use crate::e1000::stats::CountingNicContext;
+use crate::e1000::{E1000, RX_QUEUE_COUNT};
 use crate::NicContext;
 
 // Number of hardware owned descriptors to keep in reserve
-// TODO: increase if packets are processed to use multiple descriptors
 const RX_QUEUE_RESERVE: usize = 1;
 
 #[derive(Debug, PartialEq)]
@@ -27,22 +32,172 @@ impl ReceiveState {
     }
 }
 
+/// Whether the host should pass received frames to the device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RxReadiness {
+    /// Frames can be received
+    Ready,
+    /// No free descriptors left, keep frames in the backend until ready again,
+    /// frames received anyway are dropped and counted in RNBC
+    Throttled,
+    /// Receive is disabled, frames would be dropped
+    Offline,
+}
+
+/// What happened to a frame passed to receive, drops are not errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReceiveOutcome {
+    /// Placed in a receive ring
+    Received,
+    /// Dropped by receive filter, address or VLAN filtering, for being empty or oversized,
+    /// i.e. exceeding the maximum frame length or the packet buffer with header split
+    Filtered,
+    /// Dropped for lack of free receive descriptors, counted in RNBC and MPC
+    NoBuffer,
+    /// Dropped since receiving is disabled, counted in MPC
+    Offline,
+}
+
+// Split frame into the parts placed in each receive buffer, as (data, length, end of packet),
+// length may exceed data since it includes CRC compensation which is not actually written
+fn split_frame(
+    received: &[u8], received_length: usize, buffer_size: usize,
+) -> impl Iterator<Item = (&[u8], usize, bool)> {
+    (0..received_length)
+        .step_by(buffer_size)
+        .map(move |offset| {
+            let length = (received_length - offset).min(buffer_size);
+            let start = offset.min(received.len());
+            let end = (offset + length).min(received.len());
+            let eop = offset + length == received_length;
+            (&received[start..end], length, eop)
+        })
+}
+
+// Fill VLAN tag, RSS and checksum results of extended write-back descriptor
+fn set_extended_status(
+    regs: &Registers, received: &[u8], vlan_tci: Option<u16>, rss: Option<&RssResult>,
+    descriptor: &mut ReceiveDescriptorExtendedWriteBack,
+) {
+    if let Some(vlan_tci) = vlan_tci {
+        descriptor.status_vp = true;
+        descriptor.vlan = vlan_tci;
+    }
+
+    if let Some(rss) = rss {
+        descriptor.rss_type = rss.rss_type;
+        descriptor.rss_hash = rss.hash;
+        descriptor.queue = rss.queue;
+    }
+
+    // Validate checksums as enabled by RXCSUM,
+    // checksum is ignored (IXSM) if none could be validated
+    let headers = parse_headers(received, regs.vet.ethertype);
+
+    if regs.rxcsum.IPOFL {
+        if let Some(valid) = verify_ipv4_checksum(received, &headers) {
+            descriptor.status_ipcs = true;
+            descriptor.error_ipe = !valid;
+        }
+    }
+
+    if regs.rxcsum.TUOFL {
+        if let Some(valid) = verify_l4_checksum(received, &headers) {
+            let udp = headers.ip.as_ref().unwrap().protocol == IP_PROTOCOL_UDP;
+            descriptor.status_udpcs = udp;
+            descriptor.status_tcpcs = !udp;
+            descriptor.error_tcpe = !valid;
+        }
+    }
+
+    descriptor.status_ixsm =
+        !(descriptor.status_ipcs || descriptor.status_tcpcs || descriptor.status_udpcs);
+
+    // Fragments are only reported for the driver, not reassembled
+    descriptor.status_ipidv = headers.ip.as_ref().is_some_and(|ip| ip.fragment);
+}
+
 impl<C: NicContext> E1000<C> {
-    // Place received frame inside rx-ring
-    pub fn receive(&mut self, received: &[u8]) -> Result<()> {
+    /// Hand a frame from the network to the device, runs the whole receive path of filters,
+    /// VLAN tag stripping, placement in a receive ring with offload results and interrupts,
+    /// frames that are dropped along the way are reported by the outcome instead of an error
+    pub fn receive(&mut self, received: &[u8]) -> Result<ReceiveOutcome> {
+        let arrival = self.clock.now();
         debug!("Receiving {} bytes", received.len());
-        assert!(received.len() > 0, "receive called with no data");
-        assert!(
-            self.receive_state.is_ready(),
-            "receive called but nic is not ready"
-        );
-
-        let rx_ring = self
-            .rx_ring
-            .as_mut()
-            .context("RX Ring not yet initialized")?;
+        trace_frame(FrameDirection::Rx, received);
+        // Frontends may hand over empty reads, there is nothing to receive or count
+        if received.is_empty() {
+            debug!("Dropping empty frame");
+            return Ok(ReceiveOutcome::Filtered);
+        }
+
+        self.last_rx_timestamp = Some(arrival);
+
+        if let Some(monitor) = &mut self.monitor {
+            monitor(FrameDirection::Rx, received);
+        }
+
+        // Frontends may deliver frames while the driver still sets up or disables receive,
+        // these are missed like frames arriving without buffers instead of being an error
+        if self.receive_state == ReceiveState::Offline {
+            debug!("Dropping frame, receive is disabled");
+            self.regs.mpc.increment();
+            return Ok(ReceiveOutcome::Offline);
+        }
+
+        if let Some(filter) = &mut self.rx_filter {
+            if !filter(received) {
+                debug!("Dropping frame rejected by receive filter");
+                self.stats.rx_filtered_count += 1;
+                return Ok(ReceiveOutcome::Filtered);
+            }
+        }
 
-        let mut descriptor: ReceiveDescriptor = rx_ring.read_head(&mut self.nic_ctx)?;
+        // Unicast frames need to match a valid receive address unless promiscuous,
+        // multicast table is not emulated so all multicast and broadcast frames pass
+        if let Some(destination) = get_destination_address(received) {
+            if !self.regs.rctl.UPE
+                && !is_multicast_address(destination)
+                && !self.regs.is_own_address(destination)
+            {
+                debug!("Dropping unicast frame for {:x?}", destination);
+                return Ok(ReceiveOutcome::Filtered);
+            }
+        }
+
+        // Only tagged frames are filtered, untagged frames always pass
+        if self.regs.rctl.VFE {
+            if let Some(vlan_id) = get_vlan_id(received, self.regs.vet.ethertype) {
+                if !self.regs.vfta.is_allowed(vlan_id) {
+                    debug!("Dropping frame of filtered VLAN {}", vlan_id);
+                    return Ok(ReceiveOutcome::Filtered);
+                }
+            }
+        }
+
+        // Compare against maximum including CRC, regardless of it being stripped
+        let max_frame_length = self.regs.get_max_frame_length();
+        if received.len() + 4 > max_frame_length {
+            debug!(
+                "Dropping oversized frame, frame={}B, maximum={}B",
+                received.len() + 4,
+                max_frame_length
+            );
+            self.regs.roc.increment();
+            return Ok(ReceiveOutcome::Filtered);
+        }
+
+        // In VLAN mode the tag is stripped and reported in the descriptor instead,
+        // offloads and descriptor lengths refer to the untagged frame
+        let untagged = if self.regs.ctrl.VME {
+            strip_vlan_tag(received, self.regs.vet.ethertype)
+        } else {
+            None
+        };
+        let (received, vlan_tci) = match &untagged {
+            Some((frame, tci)) => (frame.as_slice(), Some(*tci)),
+            None => (received, None),
+        };
 
         // Unless SECRC (Strip Ethernet CRC) is set,
         // a Frame Check Sequence (FCS) is expected to be present at end and already checked by nic,
@@ -53,59 +208,383 @@ impl<C: NicContext> E1000<C> {
             received_length += 4;
         }
 
-        descriptor.length = received_length as u16;
-        descriptor.status_eop = true;
-        descriptor.status_dd = true;
-
+        // Frames larger than the receive buffer size are spread across multiple descriptors
+        let format = self.regs.get_receive_descriptor_format();
         let buffer_size = self.regs.rctl.get_buffer_size();
-        if received_length > buffer_size {
-            todo!(
-                "Multiple RX descriptors per packet not yet supported, buffer size={}B, packet={}B",
-                buffer_size,
-                received_length,
+        let descriptors_needed = received_length.div_ceil(buffer_size);
+        // Header split places a frame in a single descriptor, so frames exceeding its packet
+        // buffer are dropped as oversized, not missed for lack of descriptors
+        if descriptors_needed > 1 && format == ReceiveDescriptorFormat::HeaderSplit {
+            debug!(
+                "Dropping frame exceeding header split buffer, buffer size={}B, frame={}B",
+                buffer_size, received_length
             );
+            return Ok(ReceiveOutcome::Filtered);
         }
 
-        let address = descriptor.buffer as usize;
-        if address == 0 {
-            todo!("RX Descriptor null padding not yet supported");
-        }
-        self.nic_ctx.dma_prepare(address, buffer_size);
-        self.nic_ctx.dma_write(address, &received, 0);
+        // A frame is only placed if it fits completely, so there are never partially written
+        // frames for the driver to see, otherwise throttle until more descriptors are posted,
+        // unless the frame would not even fit into a completely replenished ring
+        let rss = compute_rss(&self.regs, received);
+        let Some(queue) = self.select_rx_queue(rss.as_ref(), descriptors_needed) else {
+            debug!(
+                "Dropping frame, no RX queue with {} free descriptors",
+                descriptors_needed
+            );
+            self.regs.rnbc.increment();
+            self.regs.mpc.increment();
+            let fits_replenished = self
+                .rx_rings
+                .iter()
+                .flatten()
+                .any(|rx_ring| rx_ring.capacity() >= descriptors_needed + RX_QUEUE_RESERVE);
+            if fits_replenished && self.receive_state == ReceiveState::Online {
+                self.receive_state = ReceiveState::Throttled;
+                self.events.receive_state_changed = true;
+                debug!("Throttling RX, frame does not fit ring");
+            }
+            return Ok(ReceiveOutcome::NoBuffer);
+        };
 
-        trace!("Put RX descriptor: {:?}", descriptor);
-        rx_ring.write_and_advance_head(&descriptor, &mut self.nic_ctx)?;
-        self.regs.rd_h.head = rx_ring.head as u16;
+        let free_descriptors = self.rx_rings[queue]
+            .as_ref()
+            .map_or(0, |rx_ring| rx_ring.hardware_owned_descriptors());
+        match format {
+            ReceiveDescriptorFormat::Legacy => {
+                self.receive_legacy(queue, received, received_length, vlan_tci)?
+            }
+            ReceiveDescriptorFormat::Extended => {
+                let timestamped = self.latch_rx_timestamp(arrival);
+                self.receive_extended(
+                    queue,
+                    received,
+                    received_length,
+                    vlan_tci,
+                    rss.as_ref(),
+                    timestamped,
+                )?
+            }
+            ReceiveDescriptorFormat::HeaderSplit => {
+                let timestamped = self.latch_rx_timestamp(arrival);
+                self.receive_header_split(
+                    queue,
+                    received,
+                    received_length,
+                    vlan_tci,
+                    rss.as_ref(),
+                    timestamped,
+                )?
+            }
+        }
 
         self.update_receive_state();
 
-        // Workaround: Report rxt0 even though we don't emulate any timer
-        self.report_rxt0();
+        self.check_rx_min_threshold(queue, free_descriptors);
+        self.report_rxt0_delayed();
+
+        Ok(ReceiveOutcome::Received)
+    }
+
+    // Raise RXDMT0 once free descriptors drop to the threshold given by RCTL.RDMTS,
+    // so the driver can replenish the ring before it runs empty
+    fn check_rx_min_threshold(&mut self, queue: usize, previously_free: usize) {
+        let Some(rx_ring) = &self.rx_rings[queue] else {
+            return;
+        };
+        let Some(threshold) = self.regs.rctl.get_min_threshold(rx_ring.descriptor_count()) else {
+            return;
+        };
+
+        if previously_free > threshold && rx_ring.hardware_owned_descriptors() <= threshold {
+            self.report_rxdmt0();
+        }
+    }
+
+    /// Prepare buffers of hardware owned descriptors once software posts them instead of for
+    /// every received frame, for contexts where dma_prepare is expensive
+    pub fn set_rx_buffer_prefetch(&mut self, enabled: bool) {
+        self.rx_buffer_prefetch = enabled;
+        self.prefetch_all_rx_buffers();
+    }
+
+    pub(crate) fn prefetch_all_rx_buffers(&mut self) {
+        for queue in 0..RX_QUEUE_COUNT {
+            self.prefetch_rx_buffers(queue, None);
+        }
+    }
+
+    // Prepare buffers of descriptors from start up to tail, from head if start is None,
+    // buffer addresses are at the same place in all descriptor formats
+    pub(crate) fn prefetch_rx_buffers(&mut self, queue: usize, start: Option<usize>) {
+        if !self.rx_buffer_prefetch {
+            return;
+        }
+        let Some(rx_ring) = &self.rx_rings[queue] else {
+            return;
+        };
+        let mut nic_ctx = CountingNicContext::new(&mut self.nic_ctx, &mut self.stats);
+
+        let buffer_size = self.regs.rctl.get_buffer_size();
+        let header_buffer_size = (self.regs.get_receive_descriptor_format()
+            == ReceiveDescriptorFormat::HeaderSplit)
+            .then(|| self.regs.srrctl.get_header_buffer_size());
+        let mut index = start.unwrap_or(rx_ring.head);
+        while index != rx_ring.tail {
+            let descriptor: ReceiveDescriptorSplitRead = match rx_ring.read_at(index, &mut nic_ctx)
+            {
+                Ok(descriptor) => descriptor,
+                Err(err) => {
+                    error!("Cannot prefetch RX buffers of ring {}: {}", queue, err);
+                    return;
+                }
+            };
+            if descriptor.packet_buffer != 0 {
+                nic_ctx.dma_prepare(descriptor.packet_buffer as usize, buffer_size);
+            }
+            if let Some(header_buffer_size) = header_buffer_size {
+                if descriptor.header_buffer != 0 {
+                    nic_ctx.dma_prepare(descriptor.header_buffer as usize, header_buffer_size);
+                }
+            }
+            index = rx_ring.next_index(index);
+        }
+    }
+
+    /// Arrival time of the most recently received frame, including dropped frames
+    pub fn last_rx_timestamp(&self) -> Option<Instant> {
+        self.last_rx_timestamp
+    }
+
+    // Latch arrival time in RXSTMPL/RXSTMPH if enabled and not still holding an unread one,
+    // returns whether the frame got time stamped
+    fn latch_rx_timestamp(&mut self, arrival: Instant) -> bool {
+        let tsyncrxctl = &mut self.regs.tsyncrxctl;
+        if !tsyncrxctl.EN || tsyncrxctl.RXTT {
+            return false;
+        }
+
+        let systime = arrival
+            .saturating_duration_since(self.systime_base)
+            .as_nanos() as u64;
+        self.regs.rxstmpl.value = systime as u32;
+        self.regs.rxstmph.value = (systime >> 32) as u32;
+        tsyncrxctl.RXTT = true;
+        true
+    }
+
+    // Queue selected by RSS, or round-robin if RSS is disabled,
+    // falls back to next queue with enough free descriptors if selected queue is full
+    fn select_rx_queue(
+        &mut self, rss: Option<&RssResult>, descriptors_needed: usize,
+    ) -> Option<usize> {
+        let preferred = match rss {
+            Some(rss) => rss.queue as usize,
+            None => {
+                let queue = self.next_rx_queue;
+                self.next_rx_queue = (queue + 1) % RX_QUEUE_COUNT;
+                queue
+            }
+        };
+
+        (0..RX_QUEUE_COUNT)
+            .map(|i| (preferred + i) % RX_QUEUE_COUNT)
+            .find(|&queue| match &self.rx_rings[queue] {
+                Some(rx_ring) => {
+                    rx_ring.hardware_owned_descriptors() >= descriptors_needed + RX_QUEUE_RESERVE
+                }
+                None => false,
+            })
+    }
+
+    fn receive_legacy(
+        &mut self, queue: usize, received: &[u8], received_length: usize, vlan_tci: Option<u16>,
+    ) -> Result<()> {
+        let mut nic_ctx = CountingNicContext::new(&mut self.nic_ctx, &mut self.stats);
+        let rx_ring = self.rx_rings[queue]
+            .as_mut()
+            .context("RX Ring not yet initialized")?;
+
+        let buffer_size = self.regs.rctl.get_buffer_size();
+        for (data, length, eop) in split_frame(received, received_length, buffer_size) {
+            let mut descriptor: ReceiveDescriptor = rx_ring.read_head(&mut nic_ctx)?;
+            if self.check_stale_descriptors && descriptor.status_dd {
+                warn_stale_descriptor("RX", queue, rx_ring.head);
+            }
+
+            descriptor.length = length as u16;
+            descriptor.status_eop = eop;
+            descriptor.status_dd = true;
+            // Stripped VLAN tag is only reported in the last descriptor of a frame
+            let vlan_tci = vlan_tci.filter(|_| eop);
+            descriptor.status_vp = vlan_tci.is_some();
+            descriptor.special = vlan_tci.unwrap_or(0);
+
+            // Hardware discards data for descriptors without buffer, still writing them back
+            let address = descriptor.buffer as usize;
+            if address != 0 {
+                if !self.rx_buffer_prefetch {
+                    nic_ctx.dma_prepare(address, buffer_size);
+                }
+                nic_ctx.dma_write(address, data, 0);
+            }
+
+            trace!("Put RX descriptor: {:?}", descriptor);
+            rx_ring.write_and_advance_head(&descriptor, &mut nic_ctx)?;
+        }
+        self.regs.rd[queue].h.head = rx_ring.head as u16;
+
+        Ok(())
+    }
+
+    fn receive_extended(
+        &mut self, queue: usize, received: &[u8], received_length: usize, vlan_tci: Option<u16>,
+        rss: Option<&RssResult>, timestamped: bool,
+    ) -> Result<()> {
+        let mut nic_ctx = CountingNicContext::new(&mut self.nic_ctx, &mut self.stats);
+        let rx_ring = self.rx_rings[queue]
+            .as_mut()
+            .context("RX Ring not yet initialized")?;
+
+        let buffer_size = self.regs.rctl.get_buffer_size();
+        for (data, length, eop) in split_frame(received, received_length, buffer_size) {
+            let read_descriptor: ReceiveDescriptorExtendedRead = rx_ring.read_head(&mut nic_ctx)?;
+            if self.check_stale_descriptors && read_descriptor.status_dd {
+                warn_stale_descriptor("RX", queue, rx_ring.head);
+            }
+
+            // Data for null buffers is discarded, same as for legacy descriptors
+            let address = read_descriptor.buffer as usize;
+            if address != 0 {
+                if !self.rx_buffer_prefetch {
+                    nic_ctx.dma_prepare(address, buffer_size);
+                }
+                nic_ctx.dma_write(address, data, 0);
+            }
+
+            // Status and errors are only valid in the last descriptor of a frame
+            let mut descriptor = ReceiveDescriptorExtendedWriteBack {
+                status_dd: true,
+                status_eop: eop,
+                length: length as u16,
+                ..Default::default()
+            };
+            if eop {
+                descriptor.status_tst = timestamped;
+                set_extended_status(&self.regs, received, vlan_tci, rss, &mut descriptor);
+            }
+
+            trace!("Put RX descriptor: {:?}", descriptor);
+            rx_ring.write_and_advance_head(&descriptor, &mut nic_ctx)?;
+        }
+        self.regs.rd[queue].h.head = rx_ring.head as u16;
+
+        Ok(())
+    }
+
+    // Place headers in header buffer and the remaining payload in packet buffer,
+    // if headers are not recognized or too big, the whole frame is placed in packet buffer
+    fn receive_header_split(
+        &mut self, queue: usize, received: &[u8], received_length: usize, vlan_tci: Option<u16>,
+        rss: Option<&RssResult>, timestamped: bool,
+    ) -> Result<()> {
+        let mut nic_ctx = CountingNicContext::new(&mut self.nic_ctx, &mut self.stats);
+        let rx_ring = self.rx_rings[queue]
+            .as_mut()
+            .context("RX Ring not yet initialized")?;
+
+        let read_descriptor: ReceiveDescriptorSplitRead = rx_ring.read_head(&mut nic_ctx)?;
+        // Lowest bit of the header buffer address overlaps DD of the write-back format
+        if self.check_stale_descriptors && read_descriptor.header_buffer & 1 != 0 {
+            warn_stale_descriptor("RX", queue, rx_ring.head);
+        }
+
+        let header_buffer_size = self.regs.srrctl.get_header_buffer_size();
+        let header_length = parse_headers(received, self.regs.vet.ethertype).length;
+        let split = header_length > 0
+            && header_length <= header_buffer_size
+            && read_descriptor.header_buffer != 0;
+
+        let (header, payload) = if split {
+            received.split_at(header_length)
+        } else {
+            received.split_at(0)
+        };
+
+        if split {
+            let address = read_descriptor.header_buffer as usize;
+            if !self.rx_buffer_prefetch {
+                nic_ctx.dma_prepare(address, header_buffer_size);
+            }
+            nic_ctx.dma_write(address, header, 0);
+        }
+
+        // Payload for a null packet buffer is discarded, same as for the other formats
+        let address = read_descriptor.packet_buffer as usize;
+        if !payload.is_empty() && address != 0 {
+            if !self.rx_buffer_prefetch {
+                nic_ctx.dma_prepare(address, self.regs.rctl.get_buffer_size());
+            }
+            nic_ctx.dma_write(address, payload, 0);
+        }
+
+        // Length includes CRC compensation, same as for legacy descriptors
+        let mut descriptor = ReceiveDescriptorExtendedWriteBack {
+            header_length: header.len() as u16,
+            split_header: split,
+            status_dd: true,
+            status_eop: true,
+            status_tst: timestamped,
+            length: (received_length - header.len()) as u16,
+            ..Default::default()
+        };
+        set_extended_status(&self.regs, received, vlan_tci, rss, &mut descriptor);
+
+        trace!("Put RX descriptor: {:?}", descriptor);
+        rx_ring.write_and_advance_head(&descriptor, &mut nic_ctx)?;
+        self.regs.rd[queue].h.head = rx_ring.head as u16;
 
         Ok(())
     }
 
+    pub fn rx_ready(&self) -> RxReadiness {
+        match self.receive_state {
+            ReceiveState::Online => RxReadiness::Ready,
+            ReceiveState::Throttled => RxReadiness::Throttled,
+            ReceiveState::Offline => RxReadiness::Offline,
+        }
+    }
+
     pub fn update_receive_state(&mut self) {
-        if let Some(rx_ring) = &self.rx_ring {
-            let hw_descriptors = rx_ring.hardware_owned_descriptors();
-            trace!("RX Ring: {} free descriptors remaining", hw_descriptors);
+        let mut rx_rings = self.rx_rings.iter().flatten().peekable();
+        if rx_rings.peek().is_some() {
+            // Only throttle if no queue has free descriptors left
+            let should_throttle = rx_rings.all(|rx_ring| {
+                let hw_descriptors = rx_ring.hardware_owned_descriptors();
+                trace!("RX Ring: {} free descriptors remaining", hw_descriptors);
+                hw_descriptors <= RX_QUEUE_RESERVE
+            });
 
-            let should_throttle = hw_descriptors <= RX_QUEUE_RESERVE;
             match (&self.receive_state, should_throttle) {
                 (ReceiveState::Offline, false) => {
                     self.receive_state = ReceiveState::Online;
+                    self.events.receive_ready = true;
+                    self.events.receive_state_changed = true;
                     debug!("RX enabled.");
                 }
                 (ReceiveState::Offline, true) => {
                     self.receive_state = ReceiveState::Throttled;
+                    self.events.receive_state_changed = true;
                     debug!("RX enabled but throttled.");
                 }
                 (ReceiveState::Online, true) => {
                     self.receive_state = ReceiveState::Throttled;
+                    self.events.receive_state_changed = true;
                     debug!("Throttling RX, ring full");
                 }
                 (ReceiveState::Throttled, false) => {
                     self.receive_state = ReceiveState::Online;
+                    self.events.receive_ready = true;
+                    self.events.receive_state_changed = true;
                     debug!("RX not throttled anymore.");
                 }
                 _ => {
@@ -114,6 +593,7 @@ impl<C: NicContext> E1000<C> {
             }
         } else if self.receive_state != ReceiveState::Offline {
             self.receive_state = ReceiveState::Offline;
+            self.events.receive_state_changed = true;
             debug!("RX disabled.");
         }
     }
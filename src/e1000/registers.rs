@@ -4,31 +4,49 @@
 use std::time::Duration;
 
 use anyhow::Result;
-use log::trace;
+use log::{trace, warn};
 use packed_struct::derive::PackedStruct;
 use packed_struct::prelude::{packed_bits, ReservedOne};
 use packed_struct::PackedStruct;
 
-use crate::e1000::E1000;
+use crate::e1000::frame::ETHERTYPE_VLAN;
+use crate::e1000::rss::{RETA_LENGTH, RSS_KEY_LENGTH};
+use crate::e1000::{Model, E1000, RX_QUEUE_COUNT, TX_QUEUE_COUNT};
 use crate::util::match_and_access_registers;
 use crate::NicContext;
 
+// Frame lengths including CRC
+const MAX_FRAME_LENGTH: usize = 1522; // Including VLAN tag
+const MAX_LONG_FRAME_LENGTH: usize = 16384;
+
 #[derive(Default, Debug)]
 pub struct Registers {
     // General control and status
     pub ctrl: Control,
     pub status: Status,
+    pub ctrl_ext: ExtendedControl,
 
     // Eeprom Control & Data
     pub eecd: EepromControlAndData,
+    pub eerd: EepromRead,
 
     // Management Data Interface Control, for reading/writing PHY
     pub mdic: MdiControl,
 
+    // VLAN Ether Type, identifies VLAN tagged frames
+    pub vet: VlanEtherType,
+
     // Interrupts
-    pub interrupt_throttling: InterruptDelay,
+    pub interrupt_throttling: InterruptThrottling,
+    pub rdtr: ReceiveDelayTimer,
+    pub radv: InterruptDelay, // Receive Interrupt Absolute Delay Value
     pub interrupt_cause: InterruptCauses,
     pub interrupt_mask: InterruptCauses,
+    // 82574 layout with IAM at 0xE0 and EIAC at 0xDC, operating on ICR causes, instead of the
+    // separate extended causes EICR, EIAC and EIAM at 0x1580 and up of later igb devices
+    pub iam: InterruptCauses, // Interrupt Acknowledge Auto Mask, applied on ICR reads if IAME is set
+    pub eiac: InterruptCauses, // Extended Interrupt Auto Clear, only queue causes used with MSI-X
+    pub ivar: InterruptVectorAllocation,
     // Temporary register required for mask and causes updates, since writes to them are indirect
     // IMS and IMC do not directly set mask but instead just set what bits to enable/disable
     // ICS (and probably ICR) writes do as well for causes to avoid races
@@ -38,44 +56,214 @@ pub struct Registers {
     pub rctl: ReceiveControl,
     pub tctl: TransmitControl,
 
-    // Receive descriptor
-    pub rd_ba_l: DescriptorBaseAddressLow,
-    pub rd_ba_h: DescriptorBaseAddressHigh,
-    pub rd_len: DescriptorLength,
-    pub rd_h: DescriptorHead,
-    pub rd_t: DescriptorTail,
+    pub rfctl: ReceiveFilterControl,
+    pub rxcsum: ReceiveChecksumControl,
+
+    // Receive Long Packet Maximum Length
+    pub rlpml: ReceiveLongPacketMaximumLength,
+
+    // Receive descriptor, one register block per queue
+    pub rd: [DescriptorRegisters; RX_QUEUE_COUNT],
+    pub srrctl: SplitReceiveControl,
 
-    // Transmit descriptor
-    pub td_ba_l: DescriptorBaseAddressLow,
-    pub td_ba_h: DescriptorBaseAddressHigh,
-    pub td_len: DescriptorLength,
-    pub td_h: DescriptorHead,
-    pub td_t: DescriptorTail,
+    // Transmit descriptor, one register block per queue
+    pub td: [DescriptorRegisters; TX_QUEUE_COUNT],
 
     // Receive Address 0, Ethernet MAC address
     pub ral0: ReceiveAddressLow,
     pub rah0: ReceiveAddressHigh,
+
+    // VLAN Filter Table Array, one bit per VLAN ID
+    pub vfta: VlanFilterTable,
+
+    // Receive Side Scaling
+    pub mrqc: MultipleReceiveQueuesCommand,
+    pub reta: [RawRegister; RETA_LENGTH / 4], // Redirection Table, 4 entries per register
+    pub rssrk: [RawRegister; RSS_KEY_LENGTH / 4], // RSS Random Key, 4 bytes per register
+
+    // Receive time stamping
+    pub tsyncrxctl: TimeSyncRxControl,
+    pub rxstmpl: RawRegister, // Rx time stamp low
+    pub rxstmph: RawRegister, // Rx time stamp high
+
+    // Programmed by the Linux e1000 driver during probe and open, only stored to be read back
+    pub fcal: RawRegister,    // Flow Control Address Low
+    pub fcah: RawRegister,    // Flow Control Address High
+    pub fct: RawRegister,     // Flow Control Type
+    pub fcttv: RawRegister,   // Flow Control Transmit Timer Value
+    pub fcrtl: RawRegister,   // Flow Control Receive Threshold Low
+    pub fcrth: RawRegister,   // Flow Control Receive Threshold High
+    pub ledctl: RawRegister,  // LED Control
+    pub pba: RawRegister,     // Packet Buffer Allocation
+    pub tipg: RawRegister,    // Transmit Inter Packet Gap
+    pub ait: RawRegister,     // Adaptive IFS Throttle
+    pub tidv: RawRegister,    // Transmit Interrupt Delay Value
+    pub tadv: RawRegister,    // Transmit Absolute Interrupt Delay Value
+    pub txdmac: RawRegister,  // TX DMA Control
+    pub kabgtxd: RawRegister, // AFE Band Gap Transmit Ref Data (82574)
+    pub wuc: RawRegister,     // Wake Up Control
+    pub wufc: RawRegister,    // Wake Up Filter Control
+    pub manc: RawRegister,    // Management Control, reset value 0 reports no manageability firmware
+    // Receive and Transmit Descriptor Control, per queue
+    pub rxdctl: [RawRegister; RX_QUEUE_COUNT],
+    pub txdctl: [RawRegister; TX_QUEUE_COUNT],
+    // Receive Addresses 1-15 and Multicast Table Array, not used for filtering
+    pub ra: [RawRegister; RA_LENGTH],
+    pub mta: MulticastTable,
+
+    // Statistics, collision counters always stay at zero since we only operate in full duplex
+    pub scc: StatisticsCounter,     // Single Collision Count
+    pub ecol: StatisticsCounter,    // Excessive Collisions Count
+    pub mcc: StatisticsCounter,     // Multiple Collision Count
+    pub latecol: StatisticsCounter, // Late Collisions Count
+    pub colc: StatisticsCounter,    // Collision Count
+    pub dc: StatisticsCounter,      // Defer Count
+    pub mpc: StatisticsCounter,     // Missed Packets Count
+    pub roc: StatisticsCounter,     // Receive Oversize Count
+    pub rnbc: StatisticsCounter,    // Receive No Buffers Count
+    // All other counters, always read as zero
+    unimplemented_counter: StatisticsCounter,
+
+    // Sink for writes to read-only registers, so their fields keep the device's values
+    read_only_write: RawRegister,
 }
 
 impl Registers {
     pub fn set_mac(&mut self, mac: [u8; 6]) {
         self.ral0.receive_address_low = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
         self.rah0.receive_address_high = u16::from_le_bytes([mac[4], mac[5]]);
+        self.rah0.AV = true;
     }
 
-    pub fn get_receive_descriptor_base_address(&self) -> u64 {
-        let low = (self.rd_ba_l.base_address_low as u64) << 4;
-        let high = (self.rd_ba_h.base_address_high as u64) << 32;
-        low | high
+    /// Whether a unicast destination address matches a valid receive address
+    pub fn is_own_address(&self, address: &[u8]) -> bool {
+        self.rah0.AV && address == self.get_mac()
+    }
+
+    pub fn get_mac(&self) -> [u8; 6] {
+        let low = self.ral0.receive_address_low.to_le_bytes();
+        let high = self.rah0.receive_address_high.to_le_bytes();
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+
+    pub fn get_receive_descriptor_format(&self) -> ReceiveDescriptorFormat {
+        if self.srrctl.is_header_split() {
+            ReceiveDescriptorFormat::HeaderSplit
+        } else if self.rfctl.EXSTEN {
+            ReceiveDescriptorFormat::Extended
+        } else {
+            ReceiveDescriptorFormat::Legacy
+        }
+    }
+
+    pub fn get_rss_key(&self) -> [u8; RSS_KEY_LENGTH] {
+        let mut key = [0u8; RSS_KEY_LENGTH];
+        for (chunk, register) in key.chunks_exact_mut(4).zip(&self.rssrk) {
+            chunk.copy_from_slice(&register.value.to_le_bytes());
+        }
+        key
+    }
+
+    /// Queue of redirection table entry, only 2 queues are addressable like on 82574
+    pub fn get_reta_queue(&self, index: usize) -> u8 {
+        let entry = self.reta[index / 4].value.to_le_bytes()[index % 4];
+        entry >> 7
+    }
+
+    /// Maximum length of received frames including CRC, longer frames get dropped
+    pub fn get_max_frame_length(&self) -> usize {
+        if !self.rctl.LPE {
+            return MAX_FRAME_LENGTH;
+        }
+
+        // RLPML only takes effect with long packets enabled, fall back to maximum if not programmed
+        match self.rlpml.length {
+            0 => MAX_LONG_FRAME_LENGTH,
+            length => length as usize,
+        }
+    }
+}
+
+pub const VFTA_LENGTH: usize = 128;
+pub const MTA_LENGTH: usize = 128;
+const RA_LENGTH: usize = 30; // Low and high register for 15 additional receive addresses
+
+// Wrapper since Default is not implemented for arrays of this size
+#[derive(Debug)]
+pub struct VlanFilterTable(pub [RawRegister; VFTA_LENGTH]);
+
+impl Default for VlanFilterTable {
+    fn default() -> Self {
+        Self(std::array::from_fn(|_| Default::default()))
+    }
+}
+
+impl VlanFilterTable {
+    pub fn is_allowed(&self, vlan_id: u16) -> bool {
+        let register = &self.0[vlan_id as usize / 32];
+        register.value & (1 << (vlan_id % 32)) != 0
     }
+}
+
+// Wrapper since Default is not implemented for arrays of this size
+#[derive(Debug)]
+pub struct MulticastTable(pub [RawRegister; MTA_LENGTH]);
+
+impl Default for MulticastTable {
+    fn default() -> Self {
+        Self(std::array::from_fn(|_| Default::default()))
+    }
+}
+
+// Descriptor ring registers, repeated for every queue
+#[derive(Default, Debug)]
+pub struct DescriptorRegisters {
+    pub ba_l: DescriptorBaseAddressLow,
+    pub ba_h: DescriptorBaseAddressHigh,
+    pub len: DescriptorLength,
+    pub h: DescriptorHead,
+    pub t: DescriptorTail,
+}
 
-    pub fn get_transmit_descriptor_base_address(&self) -> u64 {
-        let low = (self.td_ba_l.base_address_low as u64) << 4;
-        let high = (self.td_ba_h.base_address_high as u64) << 32;
+impl DescriptorRegisters {
+    pub fn get_base_address(&self) -> u64 {
+        let low = (self.ba_l.base_address_low as u64) << 4;
+        let high = (self.ba_h.base_address_high as u64) << 32;
         low | high
     }
 }
 
+// Rings are 16 byte aligned, the low bits of a written base address are not stored,
+// so an unaligned ring is used at the address rounded down
+fn check_base_address_alignment(data: &[u8], direction: &str, queue: usize) {
+    let unaligned_bits = data[0] & 0xF;
+    if unaligned_bits != 0 {
+        warn!(
+            "{} ring {} base address is not 16 byte aligned, ignoring low bits {:#x}",
+            direction, queue, unaligned_bits
+        );
+    }
+}
+
+// Size of the memory mapped register space
+const BAR0_SIZE: u32 = 0x20000;
+
+// Register blocks of additional queues follow the first one in 0x100 steps
+fn rx_queue(offset: u32) -> usize {
+    ((offset - 0x2800) / 0x100) as usize
+}
+
+fn tx_queue(offset: u32) -> usize {
+    ((offset - 0x3800) / 0x100) as usize
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReceiveDescriptorFormat {
+    Legacy,
+    Extended,
+    HeaderSplit,
+}
+
 fn clear(register: &mut impl Default) {
     *register = Default::default();
     trace!("Cleared register.");
@@ -84,28 +272,73 @@ fn clear(register: &mut impl Default) {
 impl<C: NicContext> E1000<C> {
     pub fn access_register(
         &mut self, offset: u32, data: &mut [u8], write: bool,
+    ) -> Option<Result<()>> {
+        self.match_register(offset, data, write, false, &mut None)
+    }
+
+    /// Registers handled for the configured model, as offset and name of the backing field,
+    /// counters that are only stubbed are named unimplemented_counter, other offsets are ignored
+    /// or go to the register fallback
+    pub fn implemented_registers(&mut self) -> Vec<(u32, &'static str)> {
+        (0..BAR0_SIZE)
+            .step_by(4)
+            .filter_map(|offset| {
+                // Some registers are write or read only
+                let name = self
+                    .register_name(offset, false)
+                    .or_else(|| self.register_name(offset, true))?;
+                Some((offset, name.trim_start_matches("self.regs.")))
+            })
+            .collect()
+    }
+
+    fn register_name(&mut self, offset: u32, write: bool) -> Option<&'static str> {
+        let mut name = None;
+        self.match_register(offset, &mut [0u8; 4], write, true, &mut name);
+        name
+    }
+
+    // If probe is set, registers are only looked up and read without side effects
+    fn match_register(
+        &mut self, offset: u32, data: &mut [u8], write: bool, probe: bool,
+        name: &mut Option<&'static str>,
     ) -> Option<Result<()>> {
         // While we could alternatively match offsets to registers and call .access(data, write)
         // after the match, that would require an additional match just to invoke actions
         // e.g. for controlling registers and registers that clear after read
         // So instead do it in one go using custom macro
-        let result = match_and_access_registers!( offset, data, write, {
+        let result = match_and_access_registers!( offset, data, write, probe(probe, *name), {
             // Offset => Register ( => and also do )
+            // STATUS, RXSTMPL and RXSTMPH are read-only
+            0x8 | 0xB624 | 0xB628 if write => self.regs.read_only_write => {
+                trace!("Ignoring write to read-only register at {:#x}", offset);
+            },
+
             0x0 => self.regs.ctrl => { if write { self.ctrl_write() } },
             0x8 => self.regs.status,
+            0x18 => self.regs.ctrl_ext,
 
             // Eeprom Control & Data
             0x10 => self.regs.eecd => { if write { self.eecd_write() } },
+            0x14 => self.regs.eerd => { if write { self.eerd_write() } },
 
             // Management Data Interface Control, for reading/writing PHY
             0x20 => self.regs.mdic => { if write { self.mdic_write() } },
 
+            0x38 => self.regs.vet,
+
             0xC4 => self.regs.interrupt_throttling,
 
             // ICR (0xC0) reads: clear-on-read, writes: out of spec but will clear specific causes
             // ICS (0xC8) writes: manually trigger interrupts, reads: out of spec but
             // real e1000 still allows ICS reads, which some drivers use to read without clear
-            0xC0 if !write => self.regs.interrupt_cause => { clear(&mut self.regs.interrupt_cause); },
+            0xC0 if !write => self.regs.interrupt_cause => {
+                clear(&mut self.regs.interrupt_cause);
+                if self.regs.ctrl_ext.IAME {
+                    self.regs.interrupt_mask.modify(&self.regs.iam, true);
+                }
+                self.update_interrupt_line();
+            },
             0xC8 if !write => self.regs.interrupt_cause,
             0xC0 | 0xC8 => self.regs.interrupt_temp => {
                 // Add causes for ICS, remove causes if ICR
@@ -120,6 +353,7 @@ impl<C: NicContext> E1000<C> {
                 if write {
                     self.interrupt();
                 }
+                self.update_interrupt_line();
             },
 
             // IMS (0xD0) for reading interrupt mask (read) and for enabling interrupts (write)
@@ -135,29 +369,121 @@ impl<C: NicContext> E1000<C> {
                     clear,
                     self.regs.interrupt_mask
                 );
+
+                // Causes that were already pending assert the interrupt as soon as unmasked
+                if !clear {
+                    self.interrupt();
+                }
+                self.update_interrupt_line();
             },
 
+            0xDC => self.regs.eiac,
+            0xE0 => self.regs.iam,
+            0xE4 if self.model == Model::I82574L => self.regs.ivar,
+
             // Receive and Transmit Control
             0x100 => self.regs.rctl => { if write { self.rctl_write() } },
             0x400 => self.regs.tctl => { if write { self.tctl_write() } },
 
-            // Receive descriptor
-            0x2800 => self.regs.rd_ba_l,
-            0x2804 => self.regs.rd_ba_h,
-            0x2808 => self.regs.rd_len,
-            0x2810 => self.regs.rd_h,
-            0x2818 => self.regs.rd_t => { if write { self.rdt_write() } },
+            0x5004 => self.regs.rlpml,
+            0x5000 => self.regs.rxcsum,
+            0x5008 => self.regs.rfctl,
+
+            0x5600..=0x57FC => self.regs.vfta.0[(offset - 0x5600) as usize / 4],
+
+            // Receive Side Scaling
+            0x5818 => self.regs.mrqc,
+            0x5C00..=0x5C7C => self.regs.reta[(offset - 0x5C00) as usize / 4],
+            0x5C80..=0x5CA4 => self.regs.rssrk[(offset - 0x5C80) as usize / 4],
+
+            // Receive time stamping, reading RXSTMPH unlocks the next time stamp
+            0xB620 => self.regs.tsyncrxctl,
+            0xB624 => self.regs.rxstmpl,
+            0xB628 if !write => self.regs.rxstmph => { self.regs.tsyncrxctl.RXTT = false; },
+
+            // Receive descriptor, queue 0 and 1
+            0x2800 | 0x2900 => self.regs.rd[rx_queue(offset)].ba_l => {
+                if write {
+                    check_base_address_alignment(data, "RX", rx_queue(offset));
+                    self.rx_ring_geometry_write(rx_queue(offset))
+                }
+            },
+            0x2804 | 0x2904 => self.regs.rd[rx_queue(offset)].ba_h => {
+                if write { self.rx_ring_geometry_write(rx_queue(offset)) }
+            },
+            0x2808 | 0x2908 => self.regs.rd[rx_queue(offset)].len => {
+                if write { self.rx_ring_geometry_write(rx_queue(offset)) }
+            },
+            0x2810 | 0x2910 => self.regs.rd[rx_queue(offset)].h => {
+                if write { self.rdh_write(rx_queue(offset)) }
+            },
+            0x2818 | 0x2918 => self.regs.rd[rx_queue(offset)].t => {
+                if write { self.rdt_write(rx_queue(offset)) }
+            },
+            0x280C => self.regs.srrctl => { if write { self.prefetch_all_rx_buffers() } },
+            0x2820 => self.regs.rdtr => { if write { self.rdtr_write() } },
+            0x282C => self.regs.radv,
 
-            // Transmit descriptor
-            0x3800 => self.regs.td_ba_l,
-            0x3804 => self.regs.td_ba_h,
-            0x3808 => self.regs.td_len,
-            0x3810 => self.regs.td_h,
-            0x3818 => self.regs.td_t => { if write { self.tdt_write() } },
+            // Transmit descriptor, queue 0 and 1
+            0x3800 | 0x3900 => self.regs.td[tx_queue(offset)].ba_l => {
+                if write {
+                    check_base_address_alignment(data, "TX", tx_queue(offset));
+                    self.tx_ring_geometry_write(tx_queue(offset))
+                }
+            },
+            0x3804 | 0x3904 => self.regs.td[tx_queue(offset)].ba_h => {
+                if write { self.tx_ring_geometry_write(tx_queue(offset)) }
+            },
+            0x3808 | 0x3908 => self.regs.td[tx_queue(offset)].len => {
+                if write { self.tx_ring_geometry_write(tx_queue(offset)) }
+            },
+            0x3810 | 0x3910 => self.regs.td[tx_queue(offset)].h => {
+                if write { self.tdh_write(tx_queue(offset)) }
+            },
+            0x3818 | 0x3918 => self.regs.td[tx_queue(offset)].t => {
+                if write { self.tdt_write(tx_queue(offset)) }
+            },
 
             // Receive Address 0, Ethernet MAC address
             0x5400 => self.regs.ral0,
             0x5404 => self.regs.rah0,
+
+            // Statistics, all clear on read
+            0x4010 if !write => self.regs.mpc => { clear(&mut self.regs.mpc); },
+            0x4014 if !write => self.regs.scc => { clear(&mut self.regs.scc); },
+            0x4018 if !write => self.regs.ecol => { clear(&mut self.regs.ecol); },
+            0x401C if !write => self.regs.mcc => { clear(&mut self.regs.mcc); },
+            0x4020 if !write => self.regs.latecol => { clear(&mut self.regs.latecol); },
+            0x4028 if !write => self.regs.colc => { clear(&mut self.regs.colc); },
+            0x4030 if !write => self.regs.dc => { clear(&mut self.regs.dc); },
+            0x40A0 if !write => self.regs.rnbc => { clear(&mut self.regs.rnbc); },
+            0x40AC if !write => self.regs.roc => { clear(&mut self.regs.roc); },
+            0x4000..=0x4124 => self.regs.unimplemented_counter => {
+                clear(&mut self.regs.unimplemented_counter);
+            },
+
+            // Stored only, as programmed by the Linux e1000 driver during probe and open
+            0x28 => self.regs.fcal,
+            0x2C => self.regs.fcah,
+            0x30 => self.regs.fct,
+            0x170 => self.regs.fcttv,
+            0x410 => self.regs.tipg,
+            0x458 => self.regs.ait,
+            0xE00 => self.regs.ledctl,
+            0x1000 => self.regs.pba,
+            0x2160 => self.regs.fcrtl,
+            0x2168 => self.regs.fcrth,
+            0x2828 | 0x2928 => self.regs.rxdctl[rx_queue(offset)],
+            0x3000 => self.regs.txdmac,
+            0x3004 => self.regs.kabgtxd,
+            0x3820 => self.regs.tidv,
+            0x382C => self.regs.tadv,
+            0x3828 | 0x3928 => self.regs.txdctl[tx_queue(offset)],
+            0x5200..=0x53FC => self.regs.mta.0[(offset - 0x5200) as usize / 4],
+            0x5408..=0x547C => self.regs.ra[(offset - 0x5408) as usize / 4],
+            0x5800 => self.regs.wuc,
+            0x5808 => self.regs.wufc,
+            0x5820 => self.regs.manc,
         } else {
             // Wildcard, if none of the above match
             return None;
@@ -210,6 +536,15 @@ pub struct Control {
 
     #[packed_field(bits = "26")]
     pub RST: bool, // Device Reset
+
+    #[packed_field(bits = "27")]
+    pub RFCE: bool, // Receive Flow Control Enable, respond to received PAUSE frames
+
+    #[packed_field(bits = "30")]
+    pub VME: bool, // VLAN Mode Enable, VLE inserts VLAN tags on transmit, tags are stripped on receive
+
+    #[packed_field(bits = "31")]
+    pub PHY_RST: bool, // PHY Reset
 }
 
 #[derive(PackedStruct, Clone, Default, Debug)]
@@ -222,6 +557,9 @@ pub struct Status {
     #[packed_field(bits = "1")]
     pub LU: bool, // Link up
 
+    #[packed_field(bits = "2:3")]
+    pub FUNC_ID: u8, // Function ID, port of multi-port cards
+
     // Always indicate 1000Mbit/s speed
     #[packed_field(bits = "6")]
     pub speed1: ReservedOne<packed_bits::Bits<1>>,
@@ -229,7 +567,54 @@ pub struct Status {
     pub speed2: ReservedOne<packed_bits::Bits<1>>,
 }
 
-// Interrupt register layouts, shared by ICR, ICS, IMS, IMC
+// Interrupt Vector Allocation (82574), MSI-X vector and valid bit per queue and other causes
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+pub struct InterruptVectorAllocation {
+    #[packed_field(bits = "0:2")]
+    pub rxq0_vector: u8,
+    #[packed_field(bits = "3")]
+    pub rxq0_valid: bool,
+
+    #[packed_field(bits = "4:6")]
+    pub rxq1_vector: u8,
+    #[packed_field(bits = "7")]
+    pub rxq1_valid: bool,
+
+    #[packed_field(bits = "8:10")]
+    pub txq0_vector: u8,
+    #[packed_field(bits = "11")]
+    pub txq0_valid: bool,
+
+    #[packed_field(bits = "12:14")]
+    pub txq1_vector: u8,
+    #[packed_field(bits = "15")]
+    pub txq1_valid: bool,
+
+    #[packed_field(bits = "16:18")]
+    pub other_vector: u8,
+    #[packed_field(bits = "19")]
+    pub other_valid: bool,
+
+    #[packed_field(bits = "31")]
+    pub tx_every_write_back: bool, // Fire TX interrupt on every write back
+}
+
+// Only IAME has an effect, other bits like DRV_LOAD are kept for drivers reading them back
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct ExtendedControl {
+    #[packed_field(bits = "0:26")]
+    other_low: u32,
+
+    #[packed_field(bits = "27")]
+    pub IAME: bool, // Interrupt Acknowledge Auto-mask Enable
+
+    #[packed_field(bits = "28:31")]
+    other_high: u8,
+}
+
+// Interrupt register layouts, shared by ICR, ICS, IMS, IMC, IAM, EIAC
 #[derive(PackedStruct, Clone, Default, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
 pub struct InterruptCauses {
@@ -242,17 +627,64 @@ pub struct InterruptCauses {
     #[packed_field(bits = "2")] // Also manually triggered by linux kernel driver
     pub LSC: bool, // Link Status Change
 
+    #[packed_field(bits = "3")]
+    pub RXSEQ: bool, // Receive Sequence Error
+
     #[packed_field(bits = "4")] // Manually triggered by linux kernel driver
     pub RXDMT0: bool, // Receive Descriptor Minimum Threshold Reached
 
+    #[packed_field(bits = "6")]
+    pub RXO: bool, // Receiver Overrun
+
     #[packed_field(bits = "7")]
     pub RXT0: bool, // Receive Timer Interrupt
 
     #[packed_field(bits = "9")]
     pub MDAC: bool, // MDI/O Access Complete
-} // Omitted a lot more causes, which are not yet emulated
+
+    #[packed_field(bits = "10")]
+    pub RXCFG: bool, // Receiving /C/ ordered sets
+
+    #[packed_field(bits = "12")]
+    pub PHYINT: bool, // PHY Interrupt
+
+    #[packed_field(bits = "13:14")]
+    pub GPI: u8, // General Purpose Interrupts, SDP6 and SDP7
+
+    #[packed_field(bits = "15")]
+    pub TXD_LOW: bool, // Transmit Descriptor Low Threshold hit
+
+    #[packed_field(bits = "16")]
+    pub SRPD: bool, // Small Receive Packet Detected
+
+    #[packed_field(bits = "17")]
+    pub ACK: bool, // Receive ACK frame detected
+
+    #[packed_field(bits = "18")]
+    pub MNG: bool, // Manageability Event
+
+    #[packed_field(bits = "20")]
+    pub RXQ0: bool, // Receive Queue 0
+
+    #[packed_field(bits = "21")]
+    pub RXQ1: bool, // Receive Queue 1
+
+    #[packed_field(bits = "22")]
+    pub TXQ0: bool, // Transmit Queue 0
+
+    #[packed_field(bits = "23")]
+    pub TXQ1: bool, // Transmit Queue 1
+
+    #[packed_field(bits = "24")]
+    pub OTHER: bool, // Other Interrupt
+} // Causes beyond TXDW, TXQE, LSC, RXDMT0, RXT0 and MDAC are never raised by the device itself
 
 impl InterruptCauses {
+    /// Causes as register value
+    pub fn bits(&self) -> u32 {
+        u32::from_be_bytes(self.pack().unwrap())
+    }
+
     fn modify(&mut self, mask: &InterruptCauses, clear: bool) {
         let previous_bits = u32::from_ne_bytes(self.pack().unwrap());
         let mask_bits = u32::from_ne_bytes(mask.pack().unwrap());
@@ -266,21 +698,56 @@ impl InterruptCauses {
     }
 }
 
+// Time increments of delay registers, ITR counts in finer steps than all others
+const ITR_INCREMENT_NANOS: u64 = 256;
+const DELAY_INCREMENT_NANOS: u64 = 1024;
+
+// Zero disables the delay, the largest interval of 0xFFFF increments is still below 68ms
+fn interval_to_duration(interval: u16, increment_nanos: u64) -> Option<Duration> {
+    (interval != 0).then(|| Duration::from_nanos(interval as u64 * increment_nanos))
+}
+
+// Interrupt Throttling, separate type from other delays to not mix up their increments
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct InterruptThrottling {
+    #[packed_field(bits = "0:15")]
+    pub interval: u16, // Minimum inter-interrupt interval in 256ns increments
+}
+
+impl InterruptThrottling {
+    pub(crate) fn get_itr_interval(&self) -> Option<Duration> {
+        interval_to_duration(self.interval, ITR_INCREMENT_NANOS)
+    }
+}
+
 #[derive(PackedStruct, Clone, Default, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
 pub struct InterruptDelay {
     #[packed_field(bits = "0:15")]
-    pub interval: u16, // Interval in 256ns increments for ITR, 1024ns for other regs
+    pub interval: u16, // Interval in 1024ns increments
 }
 
 impl InterruptDelay {
-    /// Function only for ITR! Other interrupt delay registers use different time increments!
-    pub(crate) fn get_itr_interval(&self) -> Option<Duration> {
-        if self.interval == 0 {
-            None
-        } else {
-            Some(Duration::new(0, self.interval as u32 * 256)) // 256ns increments
-        }
+    pub(crate) fn get_delay(&self) -> Option<Duration> {
+        interval_to_duration(self.interval, DELAY_INCREMENT_NANOS)
+    }
+}
+
+// Receive Interrupt Delay Timer (Packet Timer)
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct ReceiveDelayTimer {
+    #[packed_field(bits = "0:15")]
+    interval: u16, // Interval in 1024ns increments
+
+    #[packed_field(bits = "31")]
+    pub FPD: bool, // Flush Partial Descriptor Block
+}
+
+impl ReceiveDelayTimer {
+    pub(crate) fn get_delay(&self) -> Option<Duration> {
+        interval_to_duration(self.interval, DELAY_INCREMENT_NANOS)
     }
 }
 
@@ -291,9 +758,21 @@ pub struct ReceiveControl {
     #[packed_field(bits = "1")]
     pub EN: bool, // Receiver Enable
 
+    #[packed_field(bits = "3")]
+    pub UPE: bool, // Unicast Promiscuous Enabled
+
+    #[packed_field(bits = "5")]
+    pub LPE: bool, // Long Packet Reception Enable
+
+    #[packed_field(bits = "8:9")]
+    RDMTS: u8, // Receive Descriptor Minimum Threshold Size
+
     #[packed_field(bits = "16:17")]
     BSIZE: u8, // Receive Buffer Size
 
+    #[packed_field(bits = "18")]
+    pub VFE: bool, // VLAN Filter Enable
+
     #[packed_field(bits = "25")]
     BSEX: bool, // Buffer Size Extension
 
@@ -302,6 +781,8 @@ pub struct ReceiveControl {
 }
 
 impl ReceiveControl {
+    /// Size of each receive buffer, read on every frame, changing it while RX rings hold
+    /// hardware owned descriptors requires resetting the rings, as their buffers keep the old size
     pub fn get_buffer_size(&self) -> usize {
         let mut size = match self.BSIZE {
             0b00 => 2048,
@@ -316,13 +797,68 @@ impl ReceiveControl {
         }
         size
     }
+
+    // Free descriptors at which RXDMT0 is raised, as fraction of the ring length,
+    // None for the reserved encoding
+    pub fn get_min_threshold(&self, ring_length: usize) -> Option<usize> {
+        match self.RDMTS {
+            0b00 => Some(ring_length / 2),
+            0b01 => Some(ring_length / 4),
+            0b10 => Some(ring_length / 8),
+            _ => None,
+        }
+    }
 }
 
 #[derive(PackedStruct, Clone, Default, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+pub struct ReceiveFilterControl {
+    #[packed_field(bits = "15")]
+    pub EXSTEN: bool, // Extended Status Enable, use extended receive descriptors
+}
+
+// Checksum results are only reported in extended receive descriptors
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+pub struct ReceiveChecksumControl {
+    #[packed_field(bits = "0:7")]
+    PCSS: u8, // Packet Checksum Start, unused since no packet checksum is reported
+
+    #[packed_field(bits = "8")]
+    pub IPOFL: bool, // IP Checksum Offload Enable
+
+    #[packed_field(bits = "9")]
+    pub TUOFL: bool, // TCP/UDP Checksum Offload Enable
+}
+
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct ReceiveLongPacketMaximumLength {
+    #[packed_field(bits = "0:13")]
+    pub length: u16, // Maximum frame length in bytes including CRC, only used if RCTL.LPE is set
+}
+
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
 pub struct TransmitControl {
     #[packed_field(bits = "1")]
     pub EN: bool, // Transmit Enable
+
+    #[packed_field(bits = "3")]
+    pub PSP: bool, // Pad Short Packets
+
+    // Collision fields are without effect in full duplex, but keep them for driver read back
+    #[packed_field(bits = "4:11")]
+    CT: u8, // Collision Threshold
+
+    #[packed_field(bits = "12:21")]
+    COLD: u16, // Collision Distance
+
+    #[packed_field(bits = "22")]
+    SWXOFF: bool, // Software XOFF Transmission
+
+    #[packed_field(bits = "24")]
+    RTLC: bool, // Re-transmit on Late Collision
 }
 
 // Descriptor register layouts, used by rx and tx descriptor registers
@@ -361,6 +897,27 @@ pub struct DescriptorTail {
     pub tail: u16,
 }
 
+// Split and Replication Receive Control, only header split is supported
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+pub struct SplitReceiveControl {
+    #[packed_field(bits = "8:11")]
+    BSIZEHEADER: u8, // Header buffer size in 64 byte increments
+
+    #[packed_field(bits = "25:27")]
+    DESCTYPE: u8, // Descriptor type, 000b -> Legacy, 010b -> Header split
+}
+
+impl SplitReceiveControl {
+    pub fn is_header_split(&self) -> bool {
+        self.DESCTYPE == 0b010
+    }
+
+    pub fn get_header_buffer_size(&self) -> usize {
+        self.BSIZEHEADER as usize * 64
+    }
+}
+
 // Receive Address
 #[derive(PackedStruct, Clone, Default, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
@@ -374,6 +931,57 @@ pub struct ReceiveAddressLow {
 pub struct ReceiveAddressHigh {
     #[packed_field(bits = "0:15")]
     pub receive_address_high: u16,
+
+    #[packed_field(bits = "31")]
+    pub AV: bool, // Address Valid, only valid addresses are used for filtering
+}
+
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+pub struct MultipleReceiveQueuesCommand {
+    #[packed_field(bits = "0:1")]
+    MRQE: u8, // Multiple Receive Queues Enable, 00b -> Disabled, 01b -> RSS
+
+    // Enabled hash functions
+    #[packed_field(bits = "16")]
+    pub TCPIPV4: bool,
+
+    #[packed_field(bits = "17")]
+    pub IPV4: bool,
+
+    #[packed_field(bits = "20")]
+    pub IPV6: bool,
+
+    #[packed_field(bits = "21")]
+    pub TCPIPV6: bool,
+}
+
+impl MultipleReceiveQueuesCommand {
+    pub fn is_rss_enabled(&self) -> bool {
+        self.MRQE == 0b01
+    }
+}
+
+// Register without any fields, just keeping its value
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct RawRegister {
+    #[packed_field(bits = "0:31")]
+    pub value: u32,
+}
+
+// Statistics
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct StatisticsCounter {
+    #[packed_field(bits = "0:31")]
+    pub count: u32,
+}
+
+impl StatisticsCounter {
+    pub fn increment(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
 }
 
 #[derive(PackedStruct, Clone, Default, Debug)]
@@ -401,6 +1009,42 @@ pub struct EepromControlAndData {
 
     #[packed_field(bits = "8")]
     pub EE_PRES: ReservedOne<packed_bits::Bits<1>>, // EEPROM Present
+
+    #[packed_field(bits = "9")]
+    pub EE_SIZE: bool, // 82540 Microwire size, 0b -> 64 words, 1b -> 256 words
+
+    #[packed_field(bits = "10")]
+    pub EE_ADDR_BITS: bool, // Later models, 0b -> 6/8 address bits (Microwire/SPI), 1b -> 8/16
+
+    #[packed_field(bits = "13")]
+    pub EE_TYPE: bool, // 0b -> Microwire, 1b -> SPI
+}
+
+// Eeprom Read, alternative to bit-banging eeprom accesses via EECD
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct EepromRead {
+    #[packed_field(bits = "0")]
+    pub START: bool, // Start Read
+
+    #[packed_field(bits = "4")]
+    pub DONE: bool, // Read Done
+
+    #[packed_field(bits = "8:15")]
+    pub address: u8, // Read Address
+
+    #[packed_field(bits = "16:31")]
+    pub data: u16, // Read Data
+}
+
+#[derive(PackedStruct, Clone, Default, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4")]
+pub struct TimeSyncRxControl {
+    #[packed_field(bits = "0")]
+    pub RXTT: bool, // Rx Time Stamp Valid, no further frames are time stamped until RXSTMPH is read
+
+    #[packed_field(bits = "4")]
+    pub EN: bool, // Enable Rx Time Stamp
 }
 
 // Management Data Interface Control, for reading/writing PHY
@@ -422,3 +1066,18 @@ pub struct MdiControl {
     #[packed_field(bits = "29")]
     pub interrupt_enable: bool,
 }
+
+#[derive(PackedStruct, Clone, Debug)]
+#[packed_struct(bit_numbering = "lsb0", size_bytes = "4", endian = "msb")]
+pub struct VlanEtherType {
+    #[packed_field(bits = "0:15")]
+    pub ethertype: u16, // VLAN EtherType (TPID), compared against the ethertype field of frames
+}
+
+impl Default for VlanEtherType {
+    fn default() -> Self {
+        Self {
+            ethertype: ETHERTYPE_VLAN,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::e1000::frame::*;
+use crate::e1000::registers::Registers;
+
+pub const RSS_KEY_LENGTH: usize = 40;
+pub const RETA_LENGTH: usize = 128;
+
+// Hash functions as reported in extended receive descriptors
+const RSS_TYPE_TCP_IPV4: u8 = 0x1;
+const RSS_TYPE_IPV4: u8 = 0x2;
+const RSS_TYPE_TCP_IPV6: u8 = 0x3;
+const RSS_TYPE_IPV6: u8 = 0x5;
+
+const PORTS_LENGTH: usize = 4; // Source and destination port
+
+#[derive(Debug)]
+pub struct RssResult {
+    pub rss_type: u8,
+    pub hash: u32,
+    pub queue: u8,
+}
+
+pub fn toeplitz_hash(key: &[u8; RSS_KEY_LENGTH], input: &[u8]) -> u32 {
+    let mut result = 0u32;
+
+    // 32 bit window sliding over key, moved by one bit for every input bit
+    let mut window = u32::from_be_bytes([key[0], key[1], key[2], key[3]]);
+    for (i, byte) in input.iter().enumerate() {
+        let next_key_byte = key.get(i + 4).copied().unwrap_or(0);
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                result ^= window;
+            }
+            window = (window << 1) | ((next_key_byte >> (7 - bit)) & 1) as u32;
+        }
+    }
+
+    result
+}
+
+/// Hash frame according to MRQC, None if RSS is disabled or no enabled hash function applies
+pub fn compute_rss(regs: &Registers, frame: &[u8]) -> Option<RssResult> {
+    if !regs.mrqc.is_rss_enabled() {
+        return None;
+    }
+
+    let headers = parse_headers(frame, regs.vet.ethertype);
+    let ip = headers.ip.as_ref()?;
+    let tcp_ports = headers
+        .l4_offset
+        .filter(|_| ip.protocol == IP_PROTOCOL_TCP)
+        .map(|offset| &frame[offset..offset + PORTS_LENGTH]);
+
+    // Prefer hashing including TCP ports if enabled
+    let (addresses, tcp_enabled, ip_enabled, tcp_type, ip_type) = match ip.version {
+        IpVersion::V4 => (
+            ip.offset + IPV4_ADDRESSES_OFFSET
+                ..ip.offset + IPV4_ADDRESSES_OFFSET + IPV4_ADDRESSES_LENGTH,
+            regs.mrqc.TCPIPV4,
+            regs.mrqc.IPV4,
+            RSS_TYPE_TCP_IPV4,
+            RSS_TYPE_IPV4,
+        ),
+        IpVersion::V6 => (
+            ip.offset + IPV6_ADDRESSES_OFFSET
+                ..ip.offset + IPV6_ADDRESSES_OFFSET + IPV6_ADDRESSES_LENGTH,
+            regs.mrqc.TCPIPV6,
+            regs.mrqc.IPV6,
+            RSS_TYPE_TCP_IPV6,
+            RSS_TYPE_IPV6,
+        ),
+    };
+
+    let mut input = frame[addresses].to_vec();
+    let rss_type = match tcp_ports {
+        Some(ports) if tcp_enabled => {
+            input.extend_from_slice(ports);
+            tcp_type
+        }
+        _ if ip_enabled => ip_type,
+        _ => return None,
+    };
+
+    let hash = toeplitz_hash(&regs.get_rss_key(), &input);
+    // Redirection table is indexed by the 7 least significant bits of hash
+    let queue = regs.get_reta_queue(hash as usize % RETA_LENGTH);
+
+    Some(RssResult {
+        rss_type,
+        hash,
+        queue,
+    })
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::e1000::E1000;
+use crate::NicContext;
+
+/// Counts of operations the device performed through its NicContext
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub interrupt_count: u64,
+    pub dma_read_count: u64,
+    pub dma_read_bytes: u64,
+    pub dma_write_count: u64,
+    pub dma_write_bytes: u64,
+    /// Received frames dropped by the custom receive filter
+    pub rx_filtered_count: u64,
+}
+
+// Forwards to the wrapped NicContext while counting DMA operations
+pub(crate) struct CountingNicContext<'a, C: NicContext> {
+    nic_ctx: &'a mut C,
+    stats: &'a mut Stats,
+}
+
+impl<'a, C: NicContext> CountingNicContext<'a, C> {
+    pub fn new(nic_ctx: &'a mut C, stats: &'a mut Stats) -> Self {
+        CountingNicContext { nic_ctx, stats }
+    }
+}
+
+impl<C: NicContext> NicContext for CountingNicContext<'_, C> {
+    fn send(&mut self, buffer: &[u8]) -> Result<usize> {
+        self.nic_ctx.send(buffer)
+    }
+
+    fn dma_prepare(&mut self, address: usize, length: usize) {
+        self.nic_ctx.dma_prepare(address, length);
+    }
+
+    fn dma_read(&mut self, address: usize, buffer: &mut [u8], offset: usize) {
+        self.nic_ctx.dma_read(address, buffer, offset);
+        self.stats.dma_read_count += 1;
+        self.stats.dma_read_bytes += buffer.len() as u64;
+    }
+
+    fn dma_write(&mut self, address: usize, buffer: &[u8], offset: usize) {
+        self.nic_ctx.dma_write(address, buffer, offset);
+        self.stats.dma_write_count += 1;
+        self.stats.dma_write_bytes += buffer.len() as u64;
+    }
+
+    fn trigger_interrupt(&mut self) {
+        self.nic_ctx.trigger_interrupt();
+        self.stats.interrupt_count += 1;
+    }
+
+    fn trigger_interrupt_vector(&mut self, vector: u16) {
+        self.nic_ctx.trigger_interrupt_vector(vector);
+        self.stats.interrupt_count += 1;
+    }
+
+    fn interrupt_causes(&mut self, causes: u32) {
+        self.nic_ctx.interrupt_causes(causes);
+    }
+
+    fn set_timer(&mut self, duration: Duration) {
+        self.nic_ctx.set_timer(duration);
+    }
+
+    fn delete_timer(&mut self) {
+        self.nic_ctx.delete_timer();
+    }
+}
+
+impl<C: NicContext> E1000<C> {
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
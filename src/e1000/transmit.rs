@@ -1,9 +1,13 @@
+use std::time::Duration;
+
 use anyhow::{ensure, Context, Result};
 use internet_checksum::{update, Checksum};
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 
 use crate::e1000::descriptors::*;
-use crate::e1000::E1000;
+use crate::e1000::frame::{insert_vlan_tag, trace_frame, FrameDirection};
+use crate::e1000::stats::CountingNicContext;
+use crate::e1000::{E1000, TX_QUEUE_COUNT};
 use crate::util::{wrapping_add_to_u16_be_bytes, wrapping_add_to_u32_be_bytes};
 use crate::NicContext;
 
@@ -11,13 +15,29 @@ use crate::NicContext;
 const IPV4_PAYLOAD_LENGTH_OFFSET: usize = 2;
 const IPV4_IDENTIFICATION_OFFSET: usize = 4;
 const IPV6_PAYLOAD_LENGTH_OFFSET: usize = 4;
+const IPV4_HEADER_LENGTH: usize = 20; // Without options
+const IPV6_HEADER_LENGTH: usize = 40;
+const TCP_HEADER_LENGTH: usize = 20; // Without options
+const UDP_HEADER_LENGTH: usize = 8;
 const UDP_LENGTH_OFFSET: usize = 4;
 const TCP_SEQUENCE_NUMBER_OFFSET: usize = 4;
 const TCP_FLAGS_OFFSET: usize = 13; // Byte that contains FIN and PSH flag
 const TCP_FLAGS_MASK: u8 = 9; // FIN + PSH flag
+const ETHERNET_ADDRESSES_LENGTH: usize = 12; // Destination and source, followed by VLAN tag
+
+// Minimum ethernet frame length of 64 bytes, excluding 4 bytes of CRC
+const MIN_FRAME_LENGTH: usize = 60;
+// Largest jumbo frame the hardware transmits, TSO segments must not exceed it
+const MAX_FRAME_LENGTH: usize = 16128;
+// Largest descriptor sequence, a TSO header of up to 255B followed by a 20 bit payload length,
+// limits the data fetched or held for a driver never setting EOP
+const MAX_SEQUENCE_LENGTH: usize = 0xFF + 0xFFFFF;
+
+// Pause time unit of 512 bit times at 1000Mb/s
+const PAUSE_QUANTUM: Duration = Duration::from_nanos(512);
 
 #[derive(Debug, Default)]
-struct TransmitDescriptorSequence {
+pub(crate) struct TransmitDescriptorSequence {
     data: Vec<u8>,
     done: bool,
     tcp: bool,
@@ -25,18 +45,40 @@ struct TransmitDescriptorSequence {
     // Options only for tcp transmit descriptors
     insert_ip_checksum: bool,
     insert_tcp_checksum: bool,
+
+    // Checksum offset and start of legacy descriptors, taken from the last descriptor if IC is set
+    legacy_checksum: Option<(usize, usize)>,
+
+    // VLAN tag control information, taken from the last descriptor if VLE is set
+    vlan_tci: Option<u16>,
 }
 
 impl TransmitDescriptorSequence {
+    // Whether descriptors with data were added, empty null descriptors do not start a frame
+    fn is_started(&self) -> bool {
+        !self.data.is_empty() || self.tcp
+    }
+
     fn read_to_buffer(
         &mut self, address: usize, length: usize, nic_ctx: &mut dyn NicContext,
     ) -> Result<()> {
-        // Null descriptors transfer no data
-        if address == 0 {
+        // Empty descriptors transfer no data, but may still end a sequence,
+        // drivers also use null descriptors with zero length just to advance the tail
+        if length == 0 {
             return Ok(());
         }
+        ensure!(
+            address != 0,
+            "Null transmit descriptor buffer with non-zero length of {}B",
+            length
+        );
 
         let old_len = self.data.len();
+        ensure!(
+            old_len + length <= MAX_SEQUENCE_LENGTH,
+            "Transmit descriptor sequence without EOP exceeds maximum of {}B",
+            MAX_SEQUENCE_LENGTH
+        );
         self.data.resize(old_len + length, 0);
 
         nic_ctx.dma_prepare(address, length);
@@ -53,11 +95,6 @@ impl TransmitDescriptorSequence {
             TransmitDescriptorVariant::Legacy(descriptor) => {
                 ensure!(!self.tcp, "Legacy transmit descriptor in tcp sequence");
 
-                if descriptor.cmd_ic {
-                    todo!("Inserting checksum in legacy descriptor not implemented yet");
-                    // Not sure under what circumstances this is being used.
-                }
-
                 self.read_to_buffer(
                     descriptor.buffer as usize,
                     descriptor.length as usize,
@@ -65,6 +102,12 @@ impl TransmitDescriptorSequence {
                 )?;
 
                 self.done = descriptor.cmd_eop;
+                if descriptor.cmd_eop && descriptor.cmd_ic {
+                    self.legacy_checksum = Some((descriptor.cso as usize, descriptor.css as usize));
+                }
+                if descriptor.cmd_eop && descriptor.cmd_vle {
+                    self.vlan_tci = Some(descriptor.special);
+                }
             }
             TransmitDescriptorVariant::TcpContext(..) => {
                 ensure!(
@@ -96,6 +139,9 @@ impl TransmitDescriptorSequence {
                 )?;
 
                 self.done = descriptor.dcmd_eop;
+                if descriptor.dcmd_eop && descriptor.dcmd_vle {
+                    self.vlan_tci = Some(descriptor.special);
+                }
             }
         }
 
@@ -104,21 +150,77 @@ impl TransmitDescriptorSequence {
 
     // Finalize consumes self, to ensure flags are reset in next sequence
     // Could be done in place instead if this is a bottleneck
-    fn finalize(self, tcp_context: Option<&TransmitDescriptorTcpContext>) -> Result<Vec<Vec<u8>>> {
+    // VLAN tags are only inserted if VLAN mode is enabled, given by the ethertype from VET
+    fn finalize(
+        self, tcp_context: Option<&TransmitDescriptorTcpContext>, vlan_ethertype: Option<u16>,
+    ) -> Result<Vec<Vec<u8>>> {
         assert!(self.done);
 
         let mut packets: Vec<Vec<u8>> = Vec::new();
 
+        // Sequence of only empty descriptors, nothing to send
+        if self.data.is_empty() {
+            debug!("Dropping empty transmit descriptor sequence");
+            return Ok(packets);
+        }
+
         if self.tcp {
             let tcp_context =
                 tcp_context.context("TCP sequence requires TCP context descriptor")?;
 
-            // TCP Segmentation
+            // TCP/UDP Segmentation, payload is split at MSS and each segment gets a copy of the
+            // prototype headers, so UDP segments are independent datagrams with their own UDP
+            // header and checksum, not IP fragments of a single datagram as in software UFO
             if tcp_context.tucmd_tse {
                 let header_length = tcp_context.hdrlen as usize;
                 let payload_length = tcp_context.paylen as usize;
                 let segment_size = tcp_context.mss as usize;
 
+                ensure!(segment_size != 0, "TCP segmentation with zero MSS");
+                ensure!(
+                    header_length + segment_size <= MAX_FRAME_LENGTH,
+                    "TCP segment of {}B header and {}B MSS exceeds maximum frame length of {}B",
+                    header_length,
+                    segment_size,
+                    MAX_FRAME_LENGTH
+                );
+
+                ensure!(
+                    tcp_context.tucmd_ip
+                        || tcp_context.tu_css as usize
+                            >= tcp_context.ip_css as usize + IPV6_HEADER_LENGTH,
+                    "TCP/UDP header starts within IPv6 header"
+                );
+
+                // Prototype headers are updated in place for every segment, so the offsets of all
+                // updated fields have to lie within them
+                ensure!(
+                    header_length <= self.data.len(),
+                    "Header length of {}B exceeds packet of {}B",
+                    header_length,
+                    self.data.len()
+                );
+                let ip_header_length = if tcp_context.tucmd_ip {
+                    IPV4_HEADER_LENGTH
+                } else {
+                    IPV6_HEADER_LENGTH
+                };
+                let tcp_udp_header_length = if tcp_context.tucmd_tcp {
+                    TCP_HEADER_LENGTH
+                } else {
+                    UDP_HEADER_LENGTH
+                };
+                ensure!(
+                    tcp_context.ip_css as usize + ip_header_length <= header_length
+                        && tcp_context.tu_css as usize + tcp_udp_header_length <= header_length
+                        && tcp_context.tu_cso as usize + 2 <= header_length,
+                    "IP header at {}, TCP/UDP header at {} or checksum at {} exceeds {}B header",
+                    tcp_context.ip_css,
+                    tcp_context.tu_css,
+                    tcp_context.tu_cso,
+                    header_length
+                );
+
                 let prototype_header = &self.data[..header_length];
                 let segment_data = &self.data[header_length..];
 
@@ -147,7 +249,13 @@ impl TransmitDescriptorSequence {
                     packets.push(packet);
                 }
             } else {
-                packets.push(self.data);
+                // Without segmentation the packet is sent as provided by the driver, apart from
+                // lengths and checksums, the IP identification is never incremented by the device
+                let mut packet = self.data;
+                if !tcp_context.tucmd_tcp {
+                    update_udp_length(&mut packet, tcp_context, self.insert_tcp_checksum)?;
+                }
+                packets.push(packet);
             }
 
             // Fill checksums
@@ -156,18 +264,54 @@ impl TransmitDescriptorSequence {
                     let offset = tcp_context.ip_cso as usize;
                     let start = tcp_context.ip_css as usize;
                     let end = tcp_context.ip_cse as usize;
-                    write_internet_checksum(packet, offset, start, end);
+                    if let Err(err) = write_internet_checksum(packet, offset, start, end) {
+                        warn!("Skipping IP checksum insertion: {}", err);
+                    }
                 }
 
                 if self.insert_tcp_checksum {
                     let offset = tcp_context.tu_cso as usize;
                     let start = tcp_context.tu_css as usize;
                     let end = tcp_context.tu_cse as usize;
-                    write_internet_checksum(packet, offset, start, end);
+                    match write_internet_checksum(packet, offset, start, end) {
+                        Ok(()) => {
+                            // UDP uses zero for no checksum, so a computed zero is sent as all ones
+                            if !tcp_context.tucmd_tcp && packet[offset..offset + 2] == [0, 0] {
+                                packet[offset..offset + 2].copy_from_slice(&[0xFF, 0xFF]);
+                            }
+                        }
+                        Err(err) => warn!("Skipping TCP/UDP checksum insertion: {}", err),
+                    }
                 }
             }
         } else {
-            packets.push(self.data);
+            let mut packet = self.data;
+            // Checksum from CSS to end of packet, skipped if CSO or CSS point beyond the packet
+            if let Some((offset, start)) = self.legacy_checksum {
+                if start < packet.len() && offset + 2 <= packet.len() {
+                    write_internet_checksum(&mut packet, offset, start, 0)?;
+                } else {
+                    warn!(
+                        "Skipping legacy checksum insertion at {} from {}, exceeds packet of {}B",
+                        offset,
+                        start,
+                        packet.len()
+                    );
+                }
+            }
+            packets.push(packet);
+        }
+
+        // Tag is inserted after offloads, their offsets refer to the untagged frame
+        if let (Some(tci), Some(vlan_ethertype)) = (self.vlan_tci, vlan_ethertype) {
+            for packet in packets.iter_mut() {
+                ensure!(
+                    packet.len() >= ETHERNET_ADDRESSES_LENGTH,
+                    "Frame of {}B too short for VLAN tag insertion",
+                    packet.len()
+                );
+                insert_vlan_tag(packet, vlan_ethertype, tci);
+            }
         }
 
         Ok(packets)
@@ -176,109 +320,298 @@ impl TransmitDescriptorSequence {
 
 impl<C: NicContext> E1000<C> {
     pub fn process_tx_ring(&mut self) {
-        if let Some(tx_ring) = &mut self.tx_ring {
+        for queue in 0..TX_QUEUE_COUNT {
+            self.process_tx_queue(queue);
+        }
+    }
+
+    /// Simulate a PAUSE frame received from the link partner, which pauses transmission for the
+    /// given number of quanta if flow control is enabled, a quanta of 0 resumes right away
+    pub fn receive_pause(&mut self, quanta: u16) {
+        if !self.regs.ctrl.RFCE {
+            debug!("Ignoring PAUSE frame, flow control disabled");
+            return;
+        }
+        if quanta == 0 {
+            self.resume_transmit();
+            return;
+        }
+        // Timer is only available with interrupt mitigation
+        if !self.enable_interrupt_mitigation {
+            warn!("Ignoring PAUSE frame, needs timer of interrupt mitigation");
+            return;
+        }
+
+        let duration = PAUSE_QUANTUM * quanta as u32;
+        debug!("Pausing transmit for {:?}", duration);
+        self.tx_paused_until = Some(self.clock.now() + duration);
+        self.update_timer();
+    }
+
+    // Continue with descriptors queued while transmit was paused
+    pub(crate) fn resume_transmit(&mut self) {
+        if self.tx_paused_until.take().is_none() {
+            return;
+        }
+        debug!("Resuming transmit");
+        self.update_timer();
+        for queue in 0..TX_QUEUE_COUNT {
+            self.process_tx_queue(queue);
+        }
+    }
+
+    pub fn process_tx_queue(&mut self, queue: usize) {
+        if let Err(err) = self.process_tx_descriptors(queue) {
+            error!("Stopped processing TX{}: {:#}", queue, err);
+            if let Some(tx_ring) = &self.tx_rings[queue] {
+                self.regs.td[queue].h.head = tx_ring.head as u16;
+            }
+        }
+    }
+
+    fn process_tx_descriptors(&mut self, queue: usize) -> Result<()> {
+        if self.tx_paused_until.is_some() {
+            trace!("Deferring TX{}, transmit paused", queue);
+            return Ok(());
+        }
+
+        let mut nic_ctx = CountingNicContext::new(&mut self.nic_ctx, &mut self.stats);
+        if let Some(tx_ring) = &mut self.tx_rings[queue] {
             // Software wants to transmit packets
-            tx_ring.tail = self.regs.td_t.tail as usize;
+            if let Err(err) = tx_ring.update_tail(self.regs.td[queue].t.tail as usize) {
+                error!("Ignoring TDT{} write: {}", queue, err);
+                return Ok(());
+            }
 
-            let mut sequence = TransmitDescriptorSequence::default();
+            // Repeated tail write without new descriptors, queue was already reported empty
+            if tx_ring.is_empty() {
+                return Ok(());
+            }
+
+            // Continue a frame left incomplete by the previous TDT write
+            let mut sequence = self.pending_tx_sequences[queue].take().unwrap_or_default();
             let mut report_status = false;
+            // Only descriptors with RS set are written back, adjacent ones in a single transfer
+            let mut write_back = DescriptorWriteBack::new(&mut self.descriptor_scratch);
             while !tx_ring.is_empty() {
-                let mut transmit_descriptor = tx_ring.read_head(&mut self.nic_ctx).unwrap();
+                let index = tx_ring.head;
+                let mut transmit_descriptor: TransmitDescriptor =
+                    match tx_ring.read_head(&mut nic_ctx) {
+                        Ok(transmit_descriptor) => transmit_descriptor,
+                        Err(err) => {
+                            let raw = tx_ring.read_raw(index, &mut nic_ctx);
+                            log_bad_descriptor(queue, index, &raw, &err);
+                            tx_ring.advance_head();
+                            sequence = TransmitDescriptorSequence::default();
+                            continue;
+                        }
+                    };
 
                 trace!("Processing TX descriptor: {:?}", transmit_descriptor);
+                if self.check_stale_descriptors && transmit_descriptor.common.status_dd {
+                    warn_stale_descriptor("TX", queue, index);
+                }
 
-                let result = sequence.add_descriptor(&transmit_descriptor, &mut self.nic_ctx);
+                // A bad descriptor drops the frame it belongs to, the next descriptor starts a new
+                // sequence, otherwise e.g. a TCP sequence without EOP would reject all following
+                // legacy frames
+                let result = sequence.add_descriptor(&transmit_descriptor, &mut nic_ctx);
                 if let Err(err) = result {
-                    error!("Error processing transmit descriptors: {}", err);
+                    let raw = tx_ring.read_raw(index, &mut nic_ctx);
+                    log_bad_descriptor(queue, index, &raw, &err);
                     tx_ring.advance_head();
+                    sequence = TransmitDescriptorSequence::default();
                     continue;
                 }
 
                 // Done processing, report if requested
-                if transmit_descriptor.common.report_status() {
+                // RPS reports only after the frame was sent, which needs no separate handling as
+                // frames are sent right after their EOP descriptor, before the write-back is flushed
+                let common = &transmit_descriptor.common;
+                if common.report_status() || common.report_packet_sent() {
                     report_status = true;
                     transmit_descriptor.common.status_dd = true;
 
-                    tx_ring
-                        .write_and_advance_head(&transmit_descriptor, &mut self.nic_ctx)
-                        .unwrap();
+                    tx_ring.queue_write_back_and_advance_head(
+                        &transmit_descriptor,
+                        &mut write_back,
+                        &mut nic_ctx,
+                    )?;
                 } else {
                     tx_ring.advance_head();
                 }
 
+                // Drivers only send a new context when offsets change, the stored one applies to
+                // all following data descriptors of this queue, also across TDT writes
                 if let TransmitDescriptorVariant::TcpContext(desc) = transmit_descriptor.variant {
-                    self.transmit_tcp_context = Some(desc);
+                    self.transmit_tcp_contexts[queue] = Some(desc);
                 }
 
                 if sequence.done {
+                    let vlan_ethertype = self.regs.ctrl.VME.then_some(self.regs.vet.ethertype);
                     let packets = sequence
-                        .finalize(self.transmit_tcp_context.as_ref())
-                        .unwrap();
-
-                    for data in packets {
-                        let sent = self.nic_ctx.send(&data).unwrap();
-                        assert_eq!(sent, data.len(), "Did not send specified packet length");
-                        debug!("Sent {} bytes!", sent);
+                        .finalize(self.transmit_tcp_contexts[queue].as_ref(), vlan_ethertype)
+                        .unwrap_or_else(|err| {
+                            error!("Dropping transmit descriptor sequence: {}", err);
+                            Vec::new()
+                        });
+
+                    for mut data in packets {
+                        if self.regs.tctl.PSP && data.len() < MIN_FRAME_LENGTH {
+                            data.resize(MIN_FRAME_LENGTH, 0);
+                        }
+
+                        trace_frame(FrameDirection::Tx, &data);
+                        // A partially sent frame is as lost as one that failed to send
+                        let result = nic_ctx.send(&data).and_then(|sent| {
+                            ensure!(sent == data.len(), "Only {}B were sent", sent);
+                            Ok(sent)
+                        });
+                        match result {
+                            Ok(sent) => {
+                                debug!("Sent {} bytes!", sent);
+                                if let Some(monitor) = &mut self.monitor {
+                                    monitor(FrameDirection::Tx, &data);
+                                }
+                            }
+                            Err(err) => error!("Dropping {}B frame: {}", data.len(), err),
+                        }
                     }
 
                     sequence = TransmitDescriptorSequence::default();
                 }
             }
+            tx_ring.flush_write_back(&mut write_back, &mut nic_ctx);
+
+            // Like hardware, wait for the driver to queue the remaining descriptors of a frame
+            // instead of dropping or sending what was fetched so far,
+            // held data is bounded as sequences exceeding MAX_SEQUENCE_LENGTH are dropped
+            if sequence.is_started() {
+                debug!(
+                    "Holding incomplete frame of TX{} with {}B until EOP",
+                    queue,
+                    sequence.data.len()
+                );
+                self.pending_tx_sequences[queue] = Some(sequence);
+            }
 
-            self.regs.td_h.head = tx_ring.head as u16;
+            self.regs.td[queue].h.head = tx_ring.head as u16;
             if report_status {
                 self.report_txdw_and_txqe();
             } else {
                 self.report_txqe();
             }
         }
+        Ok(())
     }
 }
 
-fn write_internet_checksum(data: &mut [u8], offset: usize, start: usize, inclusive_end: usize) {
+// Offsets come from the driver's context descriptor, so they are checked against the packet
+fn write_internet_checksum(
+    data: &mut [u8], offset: usize, start: usize, inclusive_end: usize,
+) -> Result<()> {
+    let end = if inclusive_end != 0 {
+        inclusive_end + 1
+    } else {
+        data.len()
+    };
+    ensure!(
+        start <= end && end <= data.len() && offset + 2 <= data.len(),
+        "Checksum at {} over {}..{} exceeds packet of {}B",
+        offset,
+        start,
+        end,
+        data.len()
+    );
+
     // Note this range may include the checksum itself,
     // which does *not* have to be zeroed, because it is used to include partial checksums
     let mut checksum = Checksum::new();
-    if inclusive_end != 0 {
-        checksum.add_bytes(&data[start..inclusive_end + 1]);
-    } else {
-        checksum.add_bytes(&data[start..]);
-    }
+    checksum.add_bytes(&data[start..end]);
     data[offset..offset + 2].copy_from_slice(&checksum.checksum());
+    Ok(())
+}
+
+// Set UDP length of a single datagram sent without TSE to the actual length,
+// the partial checksum provided by the driver includes the length, so update it accordingly
+fn update_udp_length(
+    data: &mut [u8], tcp_context: &TransmitDescriptorTcpContext, tcp_checksum_offloaded: bool,
+) -> Result<()> {
+    let udp_offset = tcp_context.tu_css as usize;
+    let length_offset = udp_offset + UDP_LENGTH_OFFSET;
+    ensure!(
+        length_offset + 2 <= data.len(),
+        "UDP header at offset {} exceeds packet of {}B",
+        udp_offset,
+        data.len()
+    );
+    let checksum_offset = tcp_context.tu_cso as usize;
+    ensure!(
+        !tcp_checksum_offloaded || checksum_offset + 2 <= data.len(),
+        "UDP checksum at offset {} exceeds packet of {}B",
+        checksum_offset,
+        data.len()
+    );
+
+    let previous_length = [data[length_offset], data[length_offset + 1]];
+    let length = ((data.len() - udp_offset) as u16).to_be_bytes();
+    if previous_length == length {
+        return Ok(());
+    }
+    debug!(
+        "Correcting UDP length from {}B to {}B",
+        u16::from_be_bytes(previous_length),
+        u16::from_be_bytes(length)
+    );
+    data[length_offset..length_offset + 2].copy_from_slice(&length);
+
+    if tcp_checksum_offloaded {
+        let previous_checksum = [data[checksum_offset], data[checksum_offset + 1]];
+        data[checksum_offset..checksum_offset + 2].copy_from_slice(&update(
+            previous_checksum,
+            &length,
+            &previous_length, // Reversed as partial checksum is not complemented
+        ));
+    }
+
+    Ok(())
 }
 
-// Update/Fill the prototype headers prepended to the data when using TSE,
-// returns ip payload length as that needs to be included in checksum calculation
+// Update/Fill the prototype headers prepended to the data, only when using TSE,
+// TCP and UDP share IP length, identification and checksum handling, UDP segments only differ
+// by their UDP length, as there are no sequence numbers or flags to update
 fn update_prototype_headers(
     data: &mut [u8], tcp_context: &TransmitDescriptorTcpContext, segment_index: usize,
     last_frame: bool, tcp_checksum_offloaded: bool,
 ) {
-    let header_length = tcp_context.hdrlen as usize;
     let segment_size = tcp_context.mss as usize;
 
     // Checksum starts double down as offset
     let ip_offset = tcp_context.ip_css as usize;
     let tcp_udp_offset = tcp_context.tu_css as usize;
 
-    // 1. IP total length always = MSS + HDRLEN - IPCSS
-    let ip_total_length = (segment_size + header_length - ip_offset) as u16;
-    let ip_total_length = ip_total_length.to_be_bytes();
+    let length_after_ip = (data.len() - tcp_udp_offset) as u16;
 
     if tcp_context.tucmd_ip {
         // IPv4
+        // 1. IP total length = MSS + HDRLEN - IPCSS, with the last frame's payload possibly shorter
+        let ip_total_length = (data.len() - ip_offset) as u16;
         let offset = ip_offset + IPV4_PAYLOAD_LENGTH_OFFSET;
-        data[offset..offset + 2].copy_from_slice(&ip_total_length);
+        data[offset..offset + 2].copy_from_slice(&ip_total_length.to_be_bytes());
 
-        // 2. IP identification increments by 1
+        // 2. IP identification increments by 1 per segment, starting at the prototype's id
         let offset = ip_offset + IPV4_IDENTIFICATION_OFFSET;
         wrapping_add_to_u16_be_bytes(&mut data[offset..offset + 2], segment_index as u16);
     } else {
         // IPv6
+        // 1. Payload length excludes the fixed header, but includes any extension headers
+        // between fixed header and TCP/UDP header (starting at TUCSS)
+        let extension_headers_length = tcp_udp_offset - ip_offset - IPV6_HEADER_LENGTH;
+        let ip_payload_length = (extension_headers_length as u16 + length_after_ip).to_be_bytes();
         let offset = ip_offset + IPV6_PAYLOAD_LENGTH_OFFSET;
-        data[offset..offset + 2].copy_from_slice(&ip_total_length);
+        data[offset..offset + 2].copy_from_slice(&ip_payload_length);
     }
 
-    let length_after_ip = (data.len() - tcp_udp_offset) as u16;
     let length_after_ip = length_after_ip.to_be_bytes();
 
     // Length is included in TCP checksum, so update partial TCP checksum if offloaded
@@ -307,7 +640,7 @@ fn update_prototype_headers(
         }
     } else {
         // UDP
-        // 5. Set length
+        // 5. Set length, covering header and this segment's payload only
         let offset = tcp_udp_offset + UDP_LENGTH_OFFSET;
         data[offset..offset + 2].copy_from_slice(&length_after_ip);
     }
@@ -4,7 +4,7 @@ use std::time::Duration;
 
 use log::{error, LevelFilter};
 
-use crate::e1000::E1000;
+use crate::e1000::{ResetReason, Stats, E1000};
 use crate::NicContext;
 
 // General FFI interface
@@ -25,6 +25,36 @@ pub extern "C" fn initialize_rust_logging(max_level: u8) {
         .init();
 }
 
+// Capabilities of the behavioral model, as reported by e1000_capabilities
+pub const E1000_CAP_TX_CHECKSUM_OFFLOAD: u32 = 1 << 0;
+pub const E1000_CAP_TSO: u32 = 1 << 1;
+pub const E1000_CAP_RX_CHECKSUM_OFFLOAD: u32 = 1 << 2;
+pub const E1000_CAP_VLAN_FILTER: u32 = 1 << 3;
+pub const E1000_CAP_MULTI_QUEUE: u32 = 1 << 4;
+pub const E1000_CAP_RSS: u32 = 1 << 5;
+pub const E1000_CAP_HEADER_SPLIT: u32 = 1 << 6;
+pub const E1000_CAP_JUMBO_FRAMES: u32 = 1 << 7;
+pub const E1000_CAP_RX_TIMESTAMP: u32 = 1 << 8;
+pub const E1000_CAP_VLAN_INSERT: u32 = 1 << 9; // Tag insertion on transmit with VLE
+pub const E1000_CAP_VLAN_STRIP: u32 = 1 << 10; // Tag stripping on receive in VLAN mode
+
+/// Bitmask of E1000_CAP_* features implemented by this build,
+/// hosts should not advertise anything beyond them
+#[no_mangle]
+pub extern "C" fn e1000_capabilities() -> u32 {
+    E1000_CAP_TX_CHECKSUM_OFFLOAD
+        | E1000_CAP_TSO
+        | E1000_CAP_RX_CHECKSUM_OFFLOAD
+        | E1000_CAP_VLAN_FILTER
+        | E1000_CAP_MULTI_QUEUE
+        | E1000_CAP_RSS
+        | E1000_CAP_HEADER_SPLIT
+        | E1000_CAP_JUMBO_FRAMES
+        | E1000_CAP_RX_TIMESTAMP
+        | E1000_CAP_VLAN_INSERT
+        | E1000_CAP_VLAN_STRIP
+}
+
 type SendCallback = unsafe extern "C" fn(private_ptr: *mut c_void, buffer: *const u8, len: usize);
 type DmaReadCallback =
     unsafe extern "C" fn(private_ptr: *mut c_void, dma_address: usize, buffer: *mut u8, len: usize);
@@ -157,7 +187,7 @@ impl E1000FFI {
 
     #[no_mangle]
     pub extern "C" fn e1000_reset(&mut self) {
-        self.e1000.reset_e1000();
+        self.e1000.reset_e1000(ResetReason::FunctionLevel);
     }
 
     /// Process incoming data, returns true if successful
@@ -181,4 +211,10 @@ impl E1000FFI {
     pub extern "C" fn e1000_rx_should_defer(&mut self) -> bool {
         self.e1000.receive_state.should_defer()
     }
+
+    /// Interrupt and DMA statistics since creation
+    #[no_mangle]
+    pub extern "C" fn e1000_stats(&mut self) -> Stats {
+        *self.e1000.stats()
+    }
 }
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 
@@ -20,8 +20,36 @@ pub trait NicContext {
 
     fn trigger_interrupt(&mut self);
 
-    /// Set or adjust the one-shot timer
-    fn set_timer(&mut self, duration: Duration);
+    /// Trigger MSI-X vector, only used if MSI-X was enabled on a model supporting it
+    #[allow(unused_variables)]
+    fn trigger_interrupt_vector(&mut self, vector: u16) {
+        self.trigger_interrupt()
+    }
+
+    /// Called before each trigger_interrupt with the set interrupt causes as read from ICR,
+    /// allows observing what led to an interrupt
+    #[allow(unused_variables)]
+    fn interrupt_causes(&mut self, causes: u32) {} // Optional to implement
+
+    /// Set or adjust the one-shot timer,
+    /// optional if the timer is instead driven by the deadline in E1000 events
+    #[allow(unused_variables)]
+    fn set_timer(&mut self, duration: Duration) {}
     /// Delete timer, timer might not have been set before
-    fn delete_timer(&mut self);
+    fn delete_timer(&mut self) {}
+}
+
+/// Source of time for timers, can be replaced to control time, e.g. in tests
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// Monotonic system clock, used by default
+#[derive(Debug, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
 }
@@ -1,5 +1,7 @@
 // Macro to provide easier offset to register match syntax
 // and optional debugging including field names, since some registers share the same struct type
+// With probe, the matched register's name is stored in name, and if probing,
+// the register is only read into data without running actions, to look up registers
 macro_rules! match_and_access_registers {
     ($offset:expr, $data:expr, $write:expr,
     { $( $reg_offset:pat $(if $guard:expr)? => $reg:expr $( => $do:block )? ),* $(,)? }
@@ -7,21 +9,41 @@ macro_rules! match_and_access_registers {
         match $offset {
             $(
                 $reg_offset $(if $guard)? => {
-                    let result = $reg.access($data, $write);
-
-                    if $write {
-                        log::trace!("Writing {:x?} to {} -> {:?}", $data, stringify!($reg), $reg);
+                    match_and_access_registers!(@access $reg, $data, $write $(, $do)?)
+                },
+            )*
+            _ => $catch
+        }
+    };
+    ($offset:expr, $data:expr, $write:expr, probe($probe:expr, $name:expr),
+    { $( $reg_offset:pat $(if $guard:expr)? => $reg:expr $( => $do:block )? ),* $(,)? }
+    else $catch:block ) => {
+        match $offset {
+            $(
+                $reg_offset $(if $guard)? => {
+                    $name = Some(stringify!($reg));
+                    if $probe {
+                        $reg.access($data, false)
                     } else {
-                        log::trace!("Reading {}: {:?} -> {:x?}", stringify!($reg), $reg, $data);
+                        match_and_access_registers!(@access $reg, $data, $write $(, $do)?)
                     }
-
-                    $( $do )?
-                    result
                 },
             )*
             _ => $catch
         }
     };
+    (@access $reg:expr, $data:expr, $write:expr $(, $do:block)?) => {{
+        let result = $reg.access($data, $write);
+
+        if $write {
+            log::trace!("Writing {:x?} to {} -> {:?}", $data, stringify!($reg), $reg);
+        } else {
+            log::trace!("Reading {}: {:?} -> {:x?}", stringify!($reg), $reg, $data);
+        }
+
+        $( $do )?
+        result
+    }};
 }
 
 pub(crate) use match_and_access_registers;
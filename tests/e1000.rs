@@ -0,0 +1,1350 @@
+// Behaviour of the device as seen by a driver, driven through register accesses
+// and a flat guest memory
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use nic_emu::e1000::{EepromType, Model, ReceiveOutcome, ResetReason, E1000};
+use nic_emu::{Clock, NicContext};
+
+const MEMORY_SIZE: usize = 0x40000;
+const RING_SIZE: usize = 16;
+const DESCRIPTOR_LENGTH: usize = 16;
+
+// Guest memory layout
+const TX_RING_ADDRESS: usize = 0x0;
+const RX_RING_ADDRESS: usize = 0x1000;
+const TX_BUFFER_ADDRESS: usize = 0x2000;
+const RX_BUFFER_ADDRESS: usize = 0x10000;
+const TX_BUFFER_SIZE: usize = 2048;
+const RX_BUFFER_SIZE: usize = 2048; // Default of RCTL.BSIZE
+
+// Registers
+const EECD: usize = 0x10;
+const EERD: usize = 0x14;
+const ICR: usize = 0xC0;
+const ITR: usize = 0xC4;
+const ICS: usize = 0xC8;
+const IMS: usize = 0xD0;
+const IVAR: usize = 0xE4;
+const RCTL: usize = 0x100;
+const TCTL: usize = 0x400;
+const RDBAL: usize = 0x2800;
+const RDLEN: usize = 0x2808;
+const RDH: usize = 0x2810;
+const RDT: usize = 0x2818;
+const SRRCTL: usize = 0x280C;
+const RDTR: usize = 0x2820;
+const RADV: usize = 0x282C;
+const TDBAL: usize = 0x3800;
+const TDLEN: usize = 0x3808;
+const TDH: usize = 0x3810;
+const TDT: usize = 0x3818;
+const QUEUE_1: usize = 0x100; // Offset of queue 1 descriptor registers
+const MPC: usize = 0x4010;
+const SCC: usize = 0x4014;
+const ECOL: usize = 0x4018;
+const MCC: usize = 0x401C;
+const LATECOL: usize = 0x4020;
+const COLC: usize = 0x4028;
+const DC: usize = 0x4030;
+const RNBC: usize = 0x40A0;
+const ROC: usize = 0x40AC;
+const RXCSUM: usize = 0x5000;
+const RLPML: usize = 0x5004;
+const RFCTL: usize = 0x5008;
+const MRQC: usize = 0x5818;
+const RAL0: usize = 0x5400;
+const RAH0: usize = 0x5404;
+const RSSRK: usize = 0x5C80;
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_LPE: u32 = 1 << 5;
+const SRRCTL_HEADER_SPLIT: u32 = 0b010 << 25;
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const EECD_SK: u32 = 1 << 0;
+const EECD_CS: u32 = 1 << 1;
+const EECD_DI: u32 = 1 << 2;
+const EECD_DO: u32 = 1 << 3;
+const EECD_EE_SIZE: u32 = 1 << 9;
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+const RFCTL_EXSTEN: u32 = 1 << 15;
+const RXCSUM_IPOFL: u32 = 1 << 8;
+const RXCSUM_TUOFL: u32 = 1 << 9;
+const MRQC_RSS: u32 = 0b01;
+const MRQC_TCPIPV4: u32 = 1 << 16;
+const MRQC_IPV4: u32 = 1 << 17;
+const ICR_TXDW: u32 = 1 << 0;
+const ICR_TXQE: u32 = 1 << 1;
+const ICR_LSC: u32 = 1 << 2;
+const ICR_RXT0: u32 = 1 << 7;
+
+// Descriptor bits
+const STATUS_DD: u8 = 1 << 0;
+const STATUS_EOP: u8 = 1 << 1;
+const STATUS_IXSM: u8 = 1 << 2;
+const STATUS_TCPCS: u8 = 1 << 5;
+const STATUS_IPCS: u8 = 1 << 6;
+const ERROR_TCPE: u8 = 1 << 5;
+const ERROR_IPE: u8 = 1 << 6;
+const CMD_EOP: u8 = 1 << 0;
+const CMD_IFCS: u8 = 1 << 1;
+const CMD_RS: u8 = 1 << 3;
+const CMD_TSE: u8 = 1 << 2; // Only in extended descriptors
+const CMD_DEXT: u8 = 1 << 5;
+const TUCMD_TCP: u8 = 1 << 0;
+const TUCMD_IP: u8 = 1 << 1;
+const POPTS_IXSM: u8 = 1 << 0;
+const POPTS_TXSM: u8 = 1 << 1;
+
+const MAC_ADDRESS: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+#[derive(Default)]
+struct MockNicContext {
+    memory: Vec<u8>,
+    sent: Vec<Vec<u8>>,
+    interrupts: Vec<u32>,      // Causes of each triggered interrupt
+    vectors: Vec<u16>,         // MSI-X vectors triggered
+    send_limit: Option<usize>, // Backend only sends frames partially
+}
+
+impl NicContext for MockNicContext {
+    fn send(&mut self, buffer: &[u8]) -> anyhow::Result<usize> {
+        let length = buffer.len().min(self.send_limit.unwrap_or(usize::MAX));
+        self.sent.push(buffer[..length].to_vec());
+        Ok(length)
+    }
+
+    fn dma_read(&mut self, address: usize, buffer: &mut [u8], offset: usize) {
+        let start = address + offset;
+        buffer.copy_from_slice(&self.memory[start..start + buffer.len()]);
+    }
+
+    fn dma_write(&mut self, address: usize, buffer: &[u8], offset: usize) {
+        let start = address + offset;
+        self.memory[start..start + buffer.len()].copy_from_slice(buffer);
+    }
+
+    fn trigger_interrupt(&mut self) {}
+
+    fn trigger_interrupt_vector(&mut self, vector: u16) {
+        self.vectors.push(vector);
+    }
+
+    fn interrupt_causes(&mut self, causes: u32) {
+        self.interrupts.push(causes);
+    }
+}
+
+// Device time only advanced by tests, shared with the device
+#[derive(Clone)]
+struct MockClock {
+    start: Instant,
+    elapsed: Rc<Cell<Duration>>,
+}
+
+impl MockClock {
+    fn advance_to(&self, elapsed: Duration) {
+        self.elapsed.set(elapsed);
+    }
+
+    fn at(&self, elapsed: Duration) -> Instant {
+        self.start + elapsed
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+}
+
+// Counts heap allocations of the current thread, as tests run in parallel
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+// Records messages logged by the current thread while capturing, as tests run in parallel
+struct CaptureLogger;
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        CAPTURED.with(|captured| {
+            if let Some(messages) = captured.borrow_mut().as_mut() {
+                messages.push(record.args().to_string());
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+fn capture_logs(f: impl FnOnce()) -> Vec<String> {
+    static LOGGER: CaptureLogger = CaptureLogger;
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    f();
+    CAPTURED.with(|captured| captured.borrow_mut().take().unwrap())
+}
+
+fn logged_unmatched_register(messages: &[String]) -> bool {
+    messages
+        .iter()
+        .any(|message| message.contains("Unmatched register"))
+}
+
+fn new_device(model: Model) -> E1000<MockNicContext> {
+    build_device(model, false)
+}
+
+fn build_device(model: Model, interrupt_mitigation: bool) -> E1000<MockNicContext> {
+    let nic_ctx = MockNicContext {
+        memory: vec![0u8; MEMORY_SIZE],
+        ..Default::default()
+    };
+    let mut e1000 = E1000::new(nic_ctx, interrupt_mitigation);
+    e1000.set_model(model);
+    e1000.eeprom.pack_initial_eeprom();
+    e1000.eeprom.set_ethernet_address(MAC_ADDRESS);
+    e1000.reset_e1000(ResetReason::FunctionLevel);
+    e1000
+}
+
+// Interrupt mitigation and delays need the timer, which is driven by the returned clock
+fn new_mitigated_device(model: Model) -> (E1000<MockNicContext>, MockClock) {
+    let mut e1000 = build_device(model, true);
+    let clock = MockClock {
+        start: Instant::now(),
+        elapsed: Default::default(),
+    };
+    e1000.set_clock(Box::new(clock.clone()));
+    (e1000, clock)
+}
+
+// Delay registers count in increments of 1.024us
+fn delay(increments: u64) -> Duration {
+    Duration::from_nanos(increments * 1024)
+}
+
+fn write_register(e1000: &mut E1000<MockNicContext>, offset: usize, value: u32) {
+    let mut data = value.to_le_bytes();
+    e1000.region_access_bar0(offset, &mut data, true).unwrap();
+}
+
+fn read_register(e1000: &mut E1000<MockNicContext>, offset: usize) -> u32 {
+    let mut data = [0u8; 4];
+    e1000.region_access_bar0(offset, &mut data, false).unwrap();
+    u32::from_le_bytes(data)
+}
+
+fn write_memory(e1000: &mut E1000<MockNicContext>, address: usize, data: &[u8]) {
+    e1000.nic_ctx.memory[address..address + data.len()].copy_from_slice(data);
+}
+
+fn read_memory(e1000: &E1000<MockNicContext>, address: usize, length: usize) -> &[u8] {
+    &e1000.nic_ctx.memory[address..address + length]
+}
+
+// Every descriptor owns its buffer of RX_BUFFER_SIZE, placed one after the other
+fn write_rx_descriptors(e1000: &mut E1000<MockNicContext>, ring_address: usize, buffers: usize) {
+    for index in 0..RING_SIZE {
+        let buffer = (buffers + index * RX_BUFFER_SIZE) as u64;
+        write_memory(
+            e1000,
+            ring_address + index * DESCRIPTOR_LENGTH,
+            &buffer.to_le_bytes(),
+        );
+    }
+}
+
+// Bit-bang a Microwire read of opcode 110b and address, then clock out the data word
+fn microwire_read(e1000: &mut E1000<MockNicContext>, address: u16, address_bits: u16) -> u16 {
+    let input = (0b110 << address_bits) | address as u32;
+    for bit in (0..3 + address_bits).rev() {
+        let di = if input & 1 << bit != 0 { EECD_DI } else { 0 };
+        write_register(e1000, EECD, EECD_CS | di);
+        write_register(e1000, EECD, EECD_CS | di | EECD_SK);
+        write_register(e1000, EECD, EECD_CS | di);
+    }
+
+    let mut word = 0u16;
+    for _ in 0..16 {
+        write_register(e1000, EECD, EECD_CS | EECD_SK);
+        word = word << 1 | (read_register(e1000, EECD) & EECD_DO != 0) as u16;
+        write_register(e1000, EECD, EECD_CS);
+    }
+    write_register(e1000, EECD, 0);
+    word
+}
+
+fn eerd_read(e1000: &mut E1000<MockNicContext>, address: u32) -> u16 {
+    write_register(e1000, EERD, EERD_START | address << 8);
+    let eerd = read_register(e1000, EERD);
+    assert_eq!(eerd & (EERD_START | EERD_DONE), EERD_DONE);
+    (eerd >> 16) as u16
+}
+
+// Descriptors up to tail are handed to the device
+fn setup_rx_ring(e1000: &mut E1000<MockNicContext>, rctl: u32, tail: usize) {
+    write_rx_descriptors(e1000, RX_RING_ADDRESS, RX_BUFFER_ADDRESS);
+    write_register(e1000, RDBAL, RX_RING_ADDRESS as u32);
+    write_register(e1000, RDLEN, (RING_SIZE * DESCRIPTOR_LENGTH) as u32);
+    write_register(e1000, RDT, tail as u32);
+    write_register(e1000, RCTL, RCTL_EN | rctl);
+}
+
+fn rx_descriptor_status(e1000: &E1000<MockNicContext>, ring_address: usize, index: usize) -> u8 {
+    read_memory(e1000, ring_address + index * DESCRIPTOR_LENGTH + 12, 1)[0]
+}
+
+fn setup_tx_ring(e1000: &mut E1000<MockNicContext>) {
+    write_register(e1000, TDBAL, TX_RING_ADDRESS as u32);
+    write_register(e1000, TDLEN, (RING_SIZE * DESCRIPTOR_LENGTH) as u32);
+    write_register(e1000, TCTL, TCTL_EN);
+}
+
+fn write_tx_descriptor(
+    e1000: &mut E1000<MockNicContext>, index: usize, descriptor: [u8; DESCRIPTOR_LENGTH],
+) {
+    write_memory(
+        e1000,
+        TX_RING_ADDRESS + index * DESCRIPTOR_LENGTH,
+        &descriptor,
+    );
+}
+
+// Buffer of descriptor index is filled with its index as pattern
+fn legacy_descriptor(
+    e1000: &mut E1000<MockNicContext>, index: usize, length: usize, cmd: u8,
+) -> [u8; DESCRIPTOR_LENGTH] {
+    let address = TX_BUFFER_ADDRESS + index * TX_BUFFER_SIZE;
+    write_memory(e1000, address, &vec![index as u8; length]);
+
+    let mut descriptor = [0u8; DESCRIPTOR_LENGTH];
+    descriptor[0..8].copy_from_slice(&(address as u64).to_le_bytes());
+    descriptor[8..10].copy_from_slice(&(length as u16).to_le_bytes());
+    descriptor[11] = cmd;
+    descriptor
+}
+
+// Context for checksum offloads, checksums extend to the end of the frame if end is zero
+fn context_descriptor(
+    ip_css: u8, ip_cso: u8, ip_cse: u16, tu_css: u8, tu_cso: u8, tucmd: u8,
+) -> [u8; DESCRIPTOR_LENGTH] {
+    let mut descriptor = [0u8; DESCRIPTOR_LENGTH];
+    descriptor[0] = ip_css;
+    descriptor[1] = ip_cso;
+    descriptor[2..4].copy_from_slice(&ip_cse.to_le_bytes());
+    descriptor[4] = tu_css;
+    descriptor[5] = tu_cso;
+    descriptor[11] = tucmd | CMD_DEXT;
+    descriptor
+}
+
+fn set_segmentation(
+    descriptor: &mut [u8; DESCRIPTOR_LENGTH], payload_length: usize, header_length: u8, mss: u16,
+) {
+    descriptor[8..11].copy_from_slice(&(payload_length as u32).to_le_bytes()[..3]);
+    descriptor[11] |= CMD_TSE;
+    descriptor[13] = header_length;
+    descriptor[14..16].copy_from_slice(&mss.to_le_bytes());
+}
+
+// Data descriptor of type 1, its buffer follows the one of legacy descriptors with the same index
+fn data_descriptor(
+    e1000: &mut E1000<MockNicContext>, index: usize, data: &[u8], dcmd: u8, popts: u8,
+) -> [u8; DESCRIPTOR_LENGTH] {
+    let address = TX_BUFFER_ADDRESS + index * TX_BUFFER_SIZE;
+    write_memory(e1000, address, data);
+
+    let mut descriptor = [0u8; DESCRIPTOR_LENGTH];
+    descriptor[0..8].copy_from_slice(&(address as u64).to_le_bytes());
+    descriptor[8..11].copy_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+    descriptor[10] |= 1 << 4;
+    descriptor[11] = dcmd | CMD_DEXT;
+    descriptor[13] = popts;
+    descriptor
+}
+
+fn broadcast_frame(length: usize) -> Vec<u8> {
+    let mut frame = vec![0xAB; length];
+    frame[0..6].copy_from_slice(&[0xFF; 6]);
+    frame[6..12].copy_from_slice(&MAC_ADDRESS);
+    frame
+}
+
+// RFC 1071 ones' complement sum over all parts
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for byte in parts.iter().flat_map(|part| part.chunks(2)) {
+        sum += u16::from_be_bytes([byte[0], *byte.get(1).unwrap_or(&0)]) as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// IPv4/TCP frame with valid checksums, TCP header of data_offset 32-bit words
+fn tcp_frame(data_offset: usize, payload: &[u8]) -> Vec<u8> {
+    let tcp_length = data_offset * 4 + payload.len();
+    let mut frame = broadcast_frame(14);
+    frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+
+    let mut ip = [0u8; 20];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&((20 + tcp_length) as u16).to_be_bytes());
+    ip[8] = 64; // TTL
+    ip[9] = 6; // TCP
+    ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+    ip[16..20].copy_from_slice(&[10, 0, 0, 2]);
+    let checksum = internet_checksum(&[&ip]);
+    ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    let mut tcp = vec![0u8; data_offset * 4];
+    tcp[0..2].copy_from_slice(&1234u16.to_be_bytes());
+    tcp[2..4].copy_from_slice(&80u16.to_be_bytes());
+    tcp[12] = (data_offset as u8) << 4;
+    tcp.extend_from_slice(payload);
+    let mut pseudo_header = ip[12..20].to_vec();
+    pseudo_header.extend_from_slice(&[0, 6]);
+    pseudo_header.extend_from_slice(&(tcp_length as u16).to_be_bytes());
+    if tcp.len() >= 18 {
+        let checksum = internet_checksum(&[&pseudo_header, &tcp]);
+        tcp[16..18].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    frame.extend_from_slice(&ip);
+    frame.extend_from_slice(&tcp);
+    frame
+}
+
+// Receive into extended descriptors with checksum offloads, returning the written back descriptor
+fn receive_checksummed(frame: &[u8]) -> Vec<u8> {
+    let mut e1000 = new_device(Model::I82540EM);
+    write_register(&mut e1000, RFCTL, RFCTL_EXSTEN);
+    write_register(&mut e1000, RXCSUM, RXCSUM_IPOFL | RXCSUM_TUOFL);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+    assert_eq!(e1000.receive(frame).unwrap(), ReceiveOutcome::Received);
+    read_memory(&e1000, RX_RING_ADDRESS, DESCRIPTOR_LENGTH).to_vec()
+}
+
+#[test]
+fn collision_counters_read_zero() {
+    let mut e1000 = new_device(Model::I82540EM);
+
+    let messages = capture_logs(|| {
+        for offset in [SCC, ECOL, MCC, LATECOL, COLC, DC] {
+            assert_eq!(read_register(&mut e1000, offset), 0, "offset {:#x}", offset);
+        }
+    });
+    assert!(!logged_unmatched_register(&messages), "{:?}", messages);
+
+    // Unmatched accesses are logged, so their absence above is meaningful
+    let messages = capture_logs(|| {
+        read_register(&mut e1000, 0x7FFC);
+    });
+    assert!(logged_unmatched_register(&messages));
+}
+
+// Frame lengths as received exclude the 4 bytes of CRC the maximum includes
+
+#[test]
+fn receive_frame_at_rlpml_is_accepted() {
+    let mut e1000 = new_device(Model::I82540EM);
+    write_register(&mut e1000, RLPML, 3000);
+    setup_rx_ring(&mut e1000, RCTL_LPE, RING_SIZE - 1);
+
+    let outcome = e1000.receive(&broadcast_frame(3000 - 4)).unwrap();
+    assert_eq!(outcome, ReceiveOutcome::Received);
+    assert_eq!(read_register(&mut e1000, ROC), 0);
+}
+
+#[test]
+fn receive_frame_over_rlpml_counts_oversize() {
+    let mut e1000 = new_device(Model::I82540EM);
+    write_register(&mut e1000, RLPML, 3000);
+    setup_rx_ring(&mut e1000, RCTL_LPE, RING_SIZE - 1);
+
+    let outcome = e1000.receive(&broadcast_frame(3000 - 3)).unwrap();
+    assert_eq!(outcome, ReceiveOutcome::Filtered);
+    assert_eq!(read_register(&mut e1000, ROC), 1);
+    assert_eq!(e1000.rx_head(0), Some(0));
+}
+
+#[test]
+fn receive_max_frame_length_defaults() {
+    let mut e1000 = new_device(Model::I82540EM);
+    assert_eq!(read_register(&mut e1000, RLPML), 0);
+
+    // Without LPE the standard maximum of 1522 bytes applies, regardless of RLPML
+    write_register(&mut e1000, RLPML, 3000);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+    let outcome = e1000.receive(&broadcast_frame(1522 - 4)).unwrap();
+    assert_eq!(outcome, ReceiveOutcome::Received);
+    let outcome = e1000.receive(&broadcast_frame(1522 - 3)).unwrap();
+    assert_eq!(outcome, ReceiveOutcome::Filtered);
+    assert_eq!(read_register(&mut e1000, ROC), 1);
+
+    // With LPE but RLPML left at zero, frames up to 16384 bytes are accepted
+    write_register(&mut e1000, RLPML, 0);
+    write_register(&mut e1000, RCTL, RCTL_EN | RCTL_LPE);
+    let outcome = e1000.receive(&broadcast_frame(16384 - 4)).unwrap();
+    assert_eq!(outcome, ReceiveOutcome::Received);
+    let outcome = e1000.receive(&broadcast_frame(16384 - 3)).unwrap();
+    assert_eq!(outcome, ReceiveOutcome::Filtered);
+    assert_eq!(read_register(&mut e1000, ROC), 1);
+}
+
+#[test]
+fn transmit_wrapped_ring_in_order() {
+    let mut e1000 = new_device(Model::I82540EM);
+    // Head and tail programmed before enabling, close to the end of the ring
+    write_register(&mut e1000, TDH, 12);
+    write_register(&mut e1000, TDT, 12);
+    setup_tx_ring(&mut e1000);
+
+    let indices: Vec<usize> = (12..RING_SIZE).chain(0..4).collect();
+    for &index in &indices {
+        let descriptor = legacy_descriptor(&mut e1000, index, 60, CMD_EOP | CMD_IFCS | CMD_RS);
+        write_tx_descriptor(&mut e1000, index, descriptor);
+    }
+    write_register(&mut e1000, TDT, 4);
+
+    let expected: Vec<Vec<u8>> = indices.iter().map(|&index| vec![index as u8; 60]).collect();
+    assert_eq!(e1000.nic_ctx.sent, expected);
+    assert_eq!(e1000.tx_head(0), Some(4));
+    assert_eq!(read_register(&mut e1000, TDH), 4);
+
+    // Repeated tail write sends nothing again
+    write_register(&mut e1000, TDT, 4);
+    assert_eq!(e1000.nic_ctx.sent.len(), indices.len());
+}
+
+#[test]
+fn rx_ring_rebuilt_on_base_address_change() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+
+    let new_ring_address = 0x3000;
+    let new_buffers = 0x30000;
+    write_rx_descriptors(&mut e1000, new_ring_address, new_buffers);
+    let messages = capture_logs(|| {
+        write_register(&mut e1000, RDBAL, new_ring_address as u32);
+    });
+    assert!(messages
+        .iter()
+        .any(|message| message.contains("changed while set up")));
+
+    let frame = broadcast_frame(60);
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::Received);
+    assert_eq!(
+        rx_descriptor_status(&e1000, new_ring_address, 0) & STATUS_DD,
+        STATUS_DD
+    );
+    assert_eq!(
+        rx_descriptor_status(&e1000, RX_RING_ADDRESS, 0) & STATUS_DD,
+        0
+    );
+    assert_eq!(read_memory(&e1000, new_buffers, frame.len()), frame);
+}
+
+#[test]
+fn tctl_round_trip_and_short_packet_padding() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    // EN, PSP, CT of 0x0F, COLD of 0x40 and RTLC as written by the Linux driver
+    let tctl = TCTL_EN | TCTL_PSP | 0x0F << 4 | 0x40 << 12 | 1 << 24;
+    write_register(&mut e1000, TCTL, tctl);
+    assert_eq!(read_register(&mut e1000, TCTL), tctl);
+
+    // Buffer pattern differs from the zero padding
+    let descriptor = legacy_descriptor(&mut e1000, 5, 20, CMD_EOP | CMD_IFCS | CMD_RS);
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    write_register(&mut e1000, TDT, 1);
+
+    write_register(&mut e1000, TCTL, tctl & !TCTL_PSP);
+    let descriptor = legacy_descriptor(&mut e1000, 1, 20, CMD_EOP | CMD_IFCS | CMD_RS);
+    write_tx_descriptor(&mut e1000, 1, descriptor);
+    write_register(&mut e1000, TDT, 2);
+
+    let mut padded = vec![5u8; 20];
+    padded.resize(60, 0);
+    assert_eq!(e1000.nic_ctx.sent, [padded, vec![1u8; 20]]);
+}
+
+#[test]
+fn transmit_interrupt_cause_history() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+    write_register(&mut e1000, IMS, ICR_TXDW | ICR_TXQE | ICR_LSC);
+
+    let descriptor = legacy_descriptor(&mut e1000, 0, 60, CMD_EOP | CMD_IFCS | CMD_RS);
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    write_register(&mut e1000, TDT, 1);
+
+    assert_eq!(e1000.nic_ctx.interrupts, [ICR_TXDW | ICR_TXQE]);
+}
+
+#[test]
+fn extended_rx_descriptor_round_trip() {
+    let mut e1000 = new_device(Model::I82540EM);
+    write_register(&mut e1000, RFCTL, RFCTL_EXSTEN);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+
+    // Go around the ring once, so descriptors written back are reused in read format again
+    for round in 0..=RING_SIZE {
+        let index = round % RING_SIZE;
+        let length = 60 + round;
+        let frame = broadcast_frame(length);
+        assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::Received);
+
+        // Extended status at byte 8, length at byte 12 of the write-back format
+        let address = RX_RING_ADDRESS + index * DESCRIPTOR_LENGTH;
+        let descriptor = read_memory(&e1000, address, DESCRIPTOR_LENGTH);
+        assert_eq!(
+            descriptor[8] & (STATUS_DD | STATUS_EOP),
+            STATUS_DD | STATUS_EOP
+        );
+        let written_length = u16::from_le_bytes([descriptor[12], descriptor[13]]) as usize;
+        assert_eq!(written_length, length + 4); // Including CRC, as SECRC is not set
+        let buffer = RX_BUFFER_ADDRESS + index * RX_BUFFER_SIZE;
+        assert_eq!(read_memory(&e1000, buffer, length), frame);
+
+        // Driver re-posts the descriptor in read format and hands it back
+        let mut read_format = [0u8; DESCRIPTOR_LENGTH];
+        read_format[..8].copy_from_slice(&(buffer as u64).to_le_bytes());
+        write_memory(&mut e1000, address, &read_format);
+        write_register(&mut e1000, RDT, index as u32);
+    }
+}
+
+#[test]
+fn receive_tcp_checksum_valid() {
+    let descriptor = receive_checksummed(&tcp_frame(5, &[1, 2, 3, 4, 5]));
+    assert_eq!(
+        descriptor[8] & (STATUS_IXSM | STATUS_TCPCS | STATUS_IPCS),
+        STATUS_TCPCS | STATUS_IPCS
+    );
+    assert_eq!(descriptor[11] & (ERROR_TCPE | ERROR_IPE), 0);
+}
+
+#[test]
+fn receive_tcp_checksum_invalid() {
+    let mut frame = tcp_frame(5, &[1, 2, 3, 4, 5]);
+    *frame.last_mut().unwrap() ^= 0xFF;
+    let descriptor = receive_checksummed(&frame);
+    assert_eq!(
+        descriptor[8] & (STATUS_TCPCS | STATUS_IPCS),
+        STATUS_TCPCS | STATUS_IPCS
+    );
+    assert_eq!(descriptor[11] & (ERROR_TCPE | ERROR_IPE), ERROR_TCPE);
+}
+
+#[test]
+fn receive_tcp_header_below_minimum_not_checksummed() {
+    // Data offset of 4 words is shorter than the minimal TCP header, but longer than UDP's
+    let descriptor = receive_checksummed(&tcp_frame(4, &[1, 2, 3, 4, 5, 6, 7, 8]));
+    assert_eq!(descriptor[8] & (STATUS_TCPCS | STATUS_IPCS), STATUS_IPCS);
+    assert_eq!(descriptor[11] & (ERROR_TCPE | ERROR_IPE), 0);
+}
+
+// Verification suite of the Microsoft RSS specification
+const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+fn receive_rss_hashed(mrqc: u32) -> (u8, u32) {
+    let mut e1000 = new_device(Model::I82574L);
+    for (index, chunk) in RSS_KEY.chunks(4).enumerate() {
+        let value = u32::from_le_bytes(chunk.try_into().unwrap());
+        write_register(&mut e1000, RSSRK + index * 4, value);
+    }
+    write_register(&mut e1000, MRQC, MRQC_RSS | mrqc);
+    write_register(&mut e1000, RFCTL, RFCTL_EXSTEN);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+
+    // 66.9.149.187:2794 -> 161.142.100.80:1766
+    let mut frame = tcp_frame(5, &[]);
+    frame[26..30].copy_from_slice(&[66, 9, 149, 187]);
+    frame[30..34].copy_from_slice(&[161, 142, 100, 80]);
+    frame[34..36].copy_from_slice(&2794u16.to_be_bytes());
+    frame[36..38].copy_from_slice(&1766u16.to_be_bytes());
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::Received);
+
+    // RSS type in low nibble of byte 0, hash in bytes 4 to 7 of the write-back format
+    let descriptor = read_memory(&e1000, RX_RING_ADDRESS, DESCRIPTOR_LENGTH);
+    let hash = u32::from_le_bytes(descriptor[4..8].try_into().unwrap());
+    (descriptor[0] & 0xF, hash)
+}
+
+#[test]
+fn rss_hash_matches_specification() {
+    assert_eq!(
+        receive_rss_hashed(MRQC_TCPIPV4 | MRQC_IPV4),
+        (0x1, 0x51ccc178)
+    );
+    assert_eq!(receive_rss_hashed(MRQC_IPV4), (0x2, 0x323e8fc2));
+}
+
+#[test]
+fn transmit_on_second_queue() {
+    let mut e1000 = new_device(Model::I82574L);
+    let ring_address = TX_RING_ADDRESS + 0x800;
+    write_register(&mut e1000, TDBAL + QUEUE_1, ring_address as u32);
+    write_register(
+        &mut e1000,
+        TDLEN + QUEUE_1,
+        (RING_SIZE * DESCRIPTOR_LENGTH) as u32,
+    );
+    write_register(&mut e1000, TCTL, TCTL_EN);
+
+    for index in 0..2 {
+        let descriptor = legacy_descriptor(&mut e1000, index, 60, CMD_EOP | CMD_RS);
+        write_memory(
+            &mut e1000,
+            ring_address + index * DESCRIPTOR_LENGTH,
+            &descriptor,
+        );
+    }
+    write_register(&mut e1000, TDT + QUEUE_1, 2);
+
+    assert_eq!(e1000.nic_ctx.sent, vec![vec![0u8; 60], vec![1u8; 60]]);
+    assert_eq!(read_register(&mut e1000, TDH + QUEUE_1), 2);
+    assert_eq!(read_register(&mut e1000, TDH), 0);
+}
+
+#[test]
+fn receive_round_robin_without_rss() {
+    let mut e1000 = new_device(Model::I82574L);
+    let ring_address = RX_RING_ADDRESS + 0x800;
+    let buffers = RX_BUFFER_ADDRESS + RING_SIZE * RX_BUFFER_SIZE;
+    write_rx_descriptors(&mut e1000, ring_address, buffers);
+    write_register(&mut e1000, RDBAL + QUEUE_1, ring_address as u32);
+    write_register(
+        &mut e1000,
+        RDLEN + QUEUE_1,
+        (RING_SIZE * DESCRIPTOR_LENGTH) as u32,
+    );
+    write_register(&mut e1000, RDT + QUEUE_1, (RING_SIZE - 1) as u32);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+
+    // Frames alternate between queues, starting at queue 0
+    for length in [60, 61, 62, 63] {
+        assert_eq!(
+            e1000.receive(&broadcast_frame(length)).unwrap(),
+            ReceiveOutcome::Received
+        );
+    }
+    assert_eq!(read_register(&mut e1000, RDH), 2);
+    assert_eq!(read_register(&mut e1000, RDH + QUEUE_1), 2);
+    for (index, length) in [(0, 60), (1, 62)] {
+        assert_ne!(
+            rx_descriptor_status(&e1000, RX_RING_ADDRESS, index) & STATUS_DD,
+            0
+        );
+        let buffer = RX_BUFFER_ADDRESS + index * RX_BUFFER_SIZE;
+        assert_eq!(read_memory(&e1000, buffer, length), broadcast_frame(length));
+    }
+    for (index, length) in [(0, 61), (1, 63)] {
+        assert_ne!(
+            rx_descriptor_status(&e1000, ring_address, index) & STATUS_DD,
+            0
+        );
+        let buffer = buffers + index * RX_BUFFER_SIZE;
+        assert_eq!(read_memory(&e1000, buffer, length), broadcast_frame(length));
+    }
+}
+
+#[test]
+fn stats_count_receive_and_transmit() {
+    let mut e1000 = new_device(Model::I82540EM);
+    write_register(&mut e1000, IMS, ICR_RXT0 | ICR_TXDW);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+    setup_tx_ring(&mut e1000);
+    let initial = *e1000.stats();
+
+    // Frame and descriptor are written back
+    assert_eq!(
+        e1000.receive(&broadcast_frame(100)).unwrap(),
+        ReceiveOutcome::Received
+    );
+    let received = *e1000.stats();
+    assert!(received.dma_write_count >= initial.dma_write_count + 2);
+    assert!(received.dma_write_bytes >= initial.dma_write_bytes + 100 + DESCRIPTOR_LENGTH as u64);
+    assert_eq!(received.interrupt_count, initial.interrupt_count + 1);
+
+    // Descriptor and buffer are read
+    let descriptor = legacy_descriptor(&mut e1000, 0, 60, CMD_EOP | CMD_RS);
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    write_register(&mut e1000, TDT, 1);
+    assert_eq!(e1000.nic_ctx.sent.len(), 1);
+    let transmitted = *e1000.stats();
+    assert!(transmitted.dma_read_count >= received.dma_read_count + 2);
+    assert!(transmitted.dma_read_bytes >= received.dma_read_bytes + 60 + DESCRIPTOR_LENGTH as u64);
+    assert!(transmitted.dma_write_count > received.dma_write_count);
+    assert_eq!(transmitted.interrupt_count, received.interrupt_count + 1);
+}
+
+#[test]
+fn transmit_zero_length_eop_descriptor() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    let descriptor = legacy_descriptor(&mut e1000, 0, 0, CMD_EOP | CMD_RS);
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    write_register(&mut e1000, TDT, 1);
+
+    assert!(e1000.nic_ctx.sent.is_empty());
+    assert_eq!(read_register(&mut e1000, TDH), 1);
+    let status = read_memory(&e1000, TX_RING_ADDRESS + 12, 1)[0];
+    assert_ne!(status & STATUS_DD, 0);
+}
+
+#[test]
+fn transmit_null_descriptor() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    // Null buffer of zero length only advances the head, followed by a regular frame
+    let mut descriptor = [0u8; DESCRIPTOR_LENGTH];
+    descriptor[11] = CMD_EOP | CMD_RS;
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    let descriptor = legacy_descriptor(&mut e1000, 1, 60, CMD_EOP | CMD_RS);
+    write_tx_descriptor(&mut e1000, 1, descriptor);
+    write_register(&mut e1000, TDT, 2);
+
+    assert_eq!(e1000.nic_ctx.sent, [vec![1u8; 60]]);
+    assert_eq!(read_register(&mut e1000, TDH), 2);
+    for index in 0..2 {
+        let status = read_memory(&e1000, TX_RING_ADDRESS + index * DESCRIPTOR_LENGTH + 12, 1)[0];
+        assert_ne!(status & STATUS_DD, 0);
+    }
+}
+
+#[test]
+fn repeated_tail_write_reports_queue_empty_once() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+    write_register(&mut e1000, IMS, ICR_TXDW | ICR_TXQE);
+
+    let descriptor = legacy_descriptor(&mut e1000, 0, 60, CMD_EOP | CMD_RS);
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    for _ in 0..3 {
+        write_register(&mut e1000, TDT, 1);
+        read_register(&mut e1000, ICR); // Clear causes, so repeated ones would interrupt again
+    }
+
+    assert_eq!(e1000.nic_ctx.sent.len(), 1);
+    let txqe_count = e1000
+        .nic_ctx
+        .interrupts
+        .iter()
+        .filter(|&&causes| causes & ICR_TXQE != 0)
+        .count();
+    assert_eq!(txqe_count, 1);
+}
+
+#[test]
+fn mac_address_follows_receive_address_registers() {
+    let mut e1000 = new_device(Model::I82540EM);
+    assert_eq!(e1000.mac_address(), MAC_ADDRESS);
+
+    let changed = [0x02, 0x00, 0x00, 0xAA, 0xBB, 0xCC];
+    let low = u32::from_le_bytes(changed[0..4].try_into().unwrap());
+    let high = u16::from_le_bytes(changed[4..6].try_into().unwrap()) as u32;
+    write_register(&mut e1000, RAL0, low);
+    write_register(&mut e1000, RAH0, high | 1 << 31); // Address valid
+    assert_eq!(e1000.mac_address(), changed);
+}
+
+#[test]
+fn tx_ring_length_limited_to_maximum() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    // 4096 descriptors fill the maximum of 64KiB
+    write_register(&mut e1000, TDLEN, (4096 * DESCRIPTOR_LENGTH) as u32);
+    assert_eq!(e1000.tx_head(0), Some(0));
+    let descriptor = legacy_descriptor(&mut e1000, 0, 60, CMD_EOP | CMD_RS);
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    write_register(&mut e1000, TDT, 1);
+    assert_eq!(e1000.nic_ctx.sent.len(), 1);
+
+    let messages = capture_logs(|| {
+        write_register(&mut e1000, TDLEN, (4104 * DESCRIPTOR_LENGTH) as u32);
+    });
+    assert!(messages
+        .iter()
+        .any(|message| message.contains("Rejecting TX ring 0")));
+    assert_eq!(e1000.tx_head(0), None);
+}
+
+#[test]
+fn transmit_udp_checksum_offload_without_segmentation() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    // UDP length is left for the device, checksum field holds the partial pseudo header sum
+    let payload = [0x5Au8; 31];
+    let mut frame = broadcast_frame(14);
+    frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+    let mut ip = [0u8; 20];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&((20 + 8 + payload.len()) as u16).to_be_bytes());
+    ip[8] = 64;
+    ip[9] = 17;
+    ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+    ip[16..20].copy_from_slice(&[10, 0, 0, 2]);
+    frame.extend_from_slice(&ip);
+    let pseudo_header = [&ip[12..20], &[0, 17, 0, 0]].concat();
+    let mut udp = [0u8; 8];
+    udp[0..2].copy_from_slice(&5000u16.to_be_bytes());
+    udp[2..4].copy_from_slice(&6000u16.to_be_bytes());
+    udp[6..8].copy_from_slice(&(!internet_checksum(&[&pseudo_header])).to_be_bytes());
+    frame.extend_from_slice(&udp);
+    frame.extend_from_slice(&payload);
+
+    let context = context_descriptor(14, 24, 33, 34, 40, TUCMD_IP);
+    write_tx_descriptor(&mut e1000, 0, context);
+    let popts = POPTS_IXSM | POPTS_TXSM;
+    let descriptor = data_descriptor(&mut e1000, 1, &frame, CMD_EOP | CMD_RS, popts);
+    write_tx_descriptor(&mut e1000, 1, descriptor);
+    write_register(&mut e1000, TDT, 2);
+
+    assert_eq!(e1000.nic_ctx.sent.len(), 1);
+    let sent = &e1000.nic_ctx.sent[0];
+    let udp_length = 8 + payload.len() as u16;
+    assert_eq!(sent[38..40], udp_length.to_be_bytes());
+    assert_eq!(internet_checksum(&[&sent[14..34]]), 0);
+    let pseudo_header = [&sent[26..34], &[0, 17], &udp_length.to_be_bytes()].concat();
+    assert_eq!(internet_checksum(&[&pseudo_header, &sent[34..]]), 0);
+}
+
+#[test]
+fn transmit_segmentation_rejects_oversize_mss() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    let frame = tcp_frame(5, &[0x5A; 200]);
+    let mut context = context_descriptor(14, 24, 33, 34, 50, TUCMD_TCP | TUCMD_IP);
+    set_segmentation(&mut context, 200, 54, 16128);
+    write_tx_descriptor(&mut e1000, 0, context);
+    let dcmd = CMD_EOP | CMD_TSE | CMD_RS;
+    let descriptor = data_descriptor(&mut e1000, 1, &frame, dcmd, POPTS_TXSM);
+    write_tx_descriptor(&mut e1000, 1, descriptor);
+
+    let messages = capture_logs(|| write_register(&mut e1000, TDT, 2));
+    assert!(messages
+        .iter()
+        .any(|message| message.contains("exceeds maximum frame length")));
+    assert!(e1000.nic_ctx.sent.is_empty());
+    assert_eq!(read_register(&mut e1000, TDH), 2);
+}
+
+#[test]
+fn transmit_segmentation_ipv6_extension_header() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    // IPv6 header followed by 8 byte hop-by-hop options and TCP
+    let payload: Vec<u8> = (0..100).collect();
+    let mut frame = broadcast_frame(14);
+    frame[12..14].copy_from_slice(&0x86DDu16.to_be_bytes());
+    let mut ip = [0u8; 40];
+    ip[0] = 0x60;
+    ip[6] = 0; // Hop-by-hop options
+    ip[7] = 64;
+    ip[8..24].copy_from_slice(&[0xFD, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    ip[24..40].copy_from_slice(&[0xFD, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    frame.extend_from_slice(&ip);
+    frame.extend_from_slice(&[6, 0, 1, 4, 0, 0, 0, 0]); // Next header TCP, PadN option
+    let mut tcp = [0u8; 20];
+    tcp[0..2].copy_from_slice(&1234u16.to_be_bytes());
+    tcp[2..4].copy_from_slice(&80u16.to_be_bytes());
+    tcp[12] = 5 << 4;
+    let pseudo_header = [&ip[8..40], &[0, 6]].concat(); // Length is added by the device
+    tcp[16..18].copy_from_slice(&(!internet_checksum(&[&pseudo_header])).to_be_bytes());
+    frame.extend_from_slice(&tcp);
+    frame.extend_from_slice(&payload);
+
+    let mut context = context_descriptor(14, 0, 0, 62, 78, TUCMD_TCP);
+    set_segmentation(&mut context, payload.len(), 82, 60);
+    write_tx_descriptor(&mut e1000, 0, context);
+    let dcmd = CMD_EOP | CMD_TSE | CMD_RS;
+    let descriptor = data_descriptor(&mut e1000, 1, &frame, dcmd, POPTS_TXSM);
+    write_tx_descriptor(&mut e1000, 1, descriptor);
+    write_register(&mut e1000, TDT, 2);
+
+    assert_eq!(e1000.nic_ctx.sent.len(), 2);
+    for (sent, segment) in e1000.nic_ctx.sent.iter().zip(payload.chunks(60)) {
+        assert_eq!(sent.len(), 82 + segment.len());
+        assert_eq!(sent[82..], *segment);
+
+        // Payload length covers extension header, TCP header and segment
+        let tcp_length = (20 + segment.len()) as u32;
+        assert_eq!(sent[18..20], (8 + tcp_length as u16).to_be_bytes());
+        let pseudo_header = [&sent[22..54], &tcp_length.to_be_bytes(), &[0, 0, 0, 6]].concat();
+        assert_eq!(internet_checksum(&[&pseudo_header, &sent[62..]]), 0);
+    }
+}
+
+#[test]
+fn large_eeprom_reads_high_address() {
+    let mut e1000 = new_device(Model::I82540EM);
+    e1000.set_eeprom_type(EepromType::MicrowireLarge);
+    let mut image = vec![0u8; 512];
+    image[0xC5 * 2..0xC5 * 2 + 2].copy_from_slice(&0xBEEFu16.to_le_bytes());
+    e1000.eeprom.load_image(&image).unwrap();
+    e1000.reset_e1000(ResetReason::FunctionLevel);
+
+    assert_ne!(read_register(&mut e1000, EECD) & EECD_EE_SIZE, 0);
+    assert_eq!(microwire_read(&mut e1000, 0xC5, 8), 0xBEEF);
+}
+
+#[test]
+fn eeprom_image_is_read_back() {
+    let mut e1000 = new_device(Model::I82540EM);
+
+    // Words 00h - 3Fh, with a checksum word summing them up to 0xBABA
+    let mut words: Vec<u16> = (0..0x40).map(|word| 0x1100 + word).collect();
+    let sum = words[..0x3F]
+        .iter()
+        .fold(0u16, |sum, word| sum.wrapping_add(*word));
+    words[0x3F] = 0xBABAu16.wrapping_sub(sum);
+    let image: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    e1000.eeprom.load_image(&image).unwrap();
+    assert!(e1000.eeprom.verify_checksum());
+    e1000.reset_e1000(ResetReason::FunctionLevel);
+
+    assert_eq!(microwire_read(&mut e1000, 0, 6), 0x1100);
+    assert_eq!(microwire_read(&mut e1000, 0x3F, 6), words[0x3F]);
+    assert!(e1000.eeprom.load_image(&image[..100]).is_err());
+}
+
+#[test]
+fn eerd_reads_eeprom_words() {
+    let mut e1000 = new_device(Model::I82540EM);
+
+    // Words 00h - 3Fh with the ethernet address at the start, summing up to the checksum
+    let words: Vec<u16> = (0..0x40)
+        .map(|address| eerd_read(&mut e1000, address))
+        .collect();
+    assert_eq!(words[..3], [0x5452, 0x1200, 0x5634]);
+    let checksum = words.iter().fold(0u16, |sum, word| sum.wrapping_add(*word));
+    assert_eq!(checksum, 0xBABA);
+
+    // Same words as bit-banged reads
+    assert_eq!(microwire_read(&mut e1000, 0x3F, 6), words[0x3F]);
+}
+
+#[test]
+fn receive_empty_frame_is_dropped() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+
+    assert_eq!(e1000.receive(&[]).unwrap(), ReceiveOutcome::Filtered);
+    assert_eq!(e1000.rx_head(0), Some(0));
+    assert_eq!(rx_descriptor_status(&e1000, RX_RING_ADDRESS, 0), 0);
+}
+
+#[test]
+fn transmit_skips_checksum_beyond_frame() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    // IP checksum offset and range far beyond the 60 byte frame
+    let context = context_descriptor(14, 200, 300, 34, 50, TUCMD_TCP | TUCMD_IP);
+    write_tx_descriptor(&mut e1000, 0, context);
+    let dcmd = CMD_EOP | CMD_IFCS | CMD_RS;
+    let descriptor = data_descriptor(&mut e1000, 1, &[0x5A; 60], dcmd, POPTS_IXSM);
+    write_tx_descriptor(&mut e1000, 1, descriptor);
+
+    let messages = capture_logs(|| write_register(&mut e1000, TDT, 2));
+    assert!(messages
+        .iter()
+        .any(|message| message.contains("Skipping IP checksum insertion")));
+    assert_eq!(e1000.nic_ctx.sent, [vec![0x5A; 60]]);
+    assert_eq!(e1000.tx_head(0), Some(2));
+}
+
+#[test]
+fn receive_interrupt_at_absolute_delay() {
+    let (mut e1000, clock) = new_mitigated_device(Model::I82540EM);
+    write_register(&mut e1000, IMS, ICR_RXT0);
+    write_register(&mut e1000, RDTR, 100);
+    write_register(&mut e1000, RADV, 250);
+    setup_rx_ring(&mut e1000, 0, RING_SIZE - 1);
+
+    // Packets every 80 increments keep restarting the packet timer
+    for packet in 0..4 {
+        clock.advance_to(delay(packet * 80));
+        assert_eq!(
+            e1000.receive(&broadcast_frame(60)).unwrap(),
+            ReceiveOutcome::Received
+        );
+        assert!(e1000.nic_ctx.interrupts.is_empty());
+    }
+
+    // Absolute timer started by the first packet expires before the packet timer of the last
+    assert_eq!(e1000.events().timer, Some(clock.at(delay(250))));
+    clock.advance_to(delay(250));
+    e1000.timer_elapsed();
+    assert_eq!(e1000.nic_ctx.interrupts, [ICR_RXT0]);
+}
+
+#[test]
+fn mitigated_interrupt_fires_after_interval() {
+    let (mut e1000, clock) = new_mitigated_device(Model::I82540EM);
+    write_register(&mut e1000, IMS, ICR_TXDW);
+    write_register(&mut e1000, ITR, 1000); // 256us in 256ns increments
+    let interval = Duration::from_micros(256);
+
+    // First interrupt is immediate and starts the interval, the next one is deferred
+    write_register(&mut e1000, ICS, ICR_TXDW);
+    assert_eq!(e1000.nic_ctx.interrupts.len(), 1);
+    read_register(&mut e1000, ICR);
+    clock.advance_to(Duration::from_micros(10));
+    write_register(&mut e1000, ICS, ICR_TXDW);
+    assert_eq!(e1000.nic_ctx.interrupts.len(), 1);
+    assert_eq!(e1000.events().timer, Some(clock.at(interval)));
+
+    // Timer firing early does not end the interval
+    clock.advance_to(interval - Duration::from_micros(1));
+    e1000.timer_elapsed();
+    assert_eq!(e1000.nic_ctx.interrupts.len(), 1);
+
+    clock.advance_to(interval);
+    e1000.timer_elapsed();
+    assert_eq!(e1000.nic_ctx.interrupts, [ICR_TXDW, ICR_TXDW]);
+}
+
+#[test]
+fn transmit_burst_does_not_allocate() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+    write_register(&mut e1000, IMS, ICR_TXDW | ICR_TXQE);
+    e1000.nic_ctx.interrupts.reserve(1);
+
+    // Empty descriptors are read and written back in batches, but send no frames that allocate
+    for index in 0..RING_SIZE - 1 {
+        let descriptor = legacy_descriptor(&mut e1000, index, 0, CMD_EOP | CMD_RS);
+        write_tx_descriptor(&mut e1000, index, descriptor);
+    }
+    let allocations = count_allocations(|| write_register(&mut e1000, TDT, RING_SIZE as u32 - 1));
+
+    assert_eq!(allocations, 0);
+    assert_eq!(e1000.tx_head(0), Some(RING_SIZE - 1));
+    assert_eq!(e1000.tx_descriptor_done(0, RING_SIZE - 2), Some(true));
+}
+
+#[test]
+fn transmit_partial_send_drops_frame() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+    e1000.nic_ctx.send_limit = Some(50);
+
+    for index in 0..2 {
+        let descriptor = legacy_descriptor(&mut e1000, index, 100, CMD_EOP | CMD_RS);
+        write_tx_descriptor(&mut e1000, index, descriptor);
+    }
+    let messages = capture_logs(|| write_register(&mut e1000, TDT, 2));
+
+    let dropped = messages
+        .iter()
+        .filter(|message| message.contains("Dropping 100B frame: Only 50B were sent"))
+        .count();
+    assert_eq!(dropped, 2);
+    assert_eq!(read_register(&mut e1000, TDH), 2);
+    assert_eq!(e1000.tx_descriptor_done(0, 1), Some(true));
+}
+
+#[test]
+fn receive_large_frame_exceeding_ring_leaves_no_partial_descriptors() {
+    let mut e1000 = new_device(Model::I82540EM);
+    // Frame needs four descriptors and one is kept in reserve, only four are handed over
+    setup_rx_ring(&mut e1000, RCTL_LPE, 4);
+    let frame = broadcast_frame(8000);
+
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::NoBuffer);
+    assert_eq!(e1000.rx_head(0), Some(0));
+    for index in 0..RING_SIZE {
+        assert_eq!(
+            rx_descriptor_status(&e1000, RX_RING_ADDRESS, index) & STATUS_DD,
+            0
+        );
+    }
+    assert_eq!(read_register(&mut e1000, RNBC), 1);
+    assert_eq!(read_register(&mut e1000, MPC), 1);
+
+    // Once replenished the frame is spread over the descriptors, only the last with EOP
+    write_register(&mut e1000, RDT, RING_SIZE as u32 - 1);
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::Received);
+    assert_eq!(e1000.rx_head(0), Some(4));
+    for index in 0..4 {
+        let status = rx_descriptor_status(&e1000, RX_RING_ADDRESS, index);
+        assert_eq!(status & STATUS_DD, STATUS_DD);
+        assert_eq!(status & STATUS_EOP != 0, index == 3);
+    }
+    assert_eq!(read_memory(&e1000, RX_BUFFER_ADDRESS, frame.len()), frame);
+}
+
+#[test]
+fn receive_header_split_oversize_is_not_missed() {
+    let mut e1000 = new_device(Model::I82574L);
+    write_register(&mut e1000, SRRCTL, SRRCTL_HEADER_SPLIT);
+    setup_rx_ring(&mut e1000, RCTL_LPE, RING_SIZE - 1);
+
+    // Header split places a frame in a single packet buffer of 2048B
+    let frame = broadcast_frame(3000);
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::Filtered);
+    assert_eq!(read_register(&mut e1000, MPC), 0);
+    assert_eq!(read_register(&mut e1000, RNBC), 0);
+    assert_eq!(e1000.rx_head(0), Some(0));
+}
+
+#[test]
+fn msix_causes_are_routed_by_ivar() {
+    let mut e1000 = new_device(Model::I82574L);
+    e1000.set_msix_enabled(true);
+    write_register(&mut e1000, IMS, u32::MAX);
+
+    // RX queue 0 to vector 1, TX queue 0 to vector 2, other causes to vector 4, all valid
+    let rxq0 = 1 | 1 << 3;
+    let txq0 = (2 | 1 << 3) << 8;
+    let other = (4 | 1 << 3) << 16;
+    write_register(&mut e1000, IVAR, rxq0 | txq0 | other);
+    assert_eq!(read_register(&mut e1000, IVAR), rxq0 | txq0 | other);
+
+    for (cause, vector) in [(ICR_RXT0, 1), (ICR_TXDW, 2), (ICR_LSC, 4)] {
+        e1000.nic_ctx.vectors.clear();
+        write_register(&mut e1000, ICS, cause);
+        assert_eq!(e1000.nic_ctx.vectors, [vector], "cause {:#x}", cause);
+        read_register(&mut e1000, ICR);
+    }
+
+    // Vector beyond the five of the MSI-X table is not triggered
+    e1000.nic_ctx.vectors.clear();
+    write_register(&mut e1000, IVAR, (7 | 1 << 3) << 16);
+    write_register(&mut e1000, ICS, ICR_LSC);
+    assert!(e1000.nic_ctx.vectors.is_empty());
+}
+
+#[test]
+fn transmit_skips_descriptor_of_unknown_type() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    let mut descriptor = legacy_descriptor(&mut e1000, 0, 60, CMD_EOP | CMD_IFCS | CMD_DEXT);
+    descriptor[10] = 0xF << 4; // DTYP 0xF
+    write_tx_descriptor(&mut e1000, 0, descriptor);
+    let descriptor = legacy_descriptor(&mut e1000, 1, 60, CMD_EOP | CMD_IFCS | CMD_RS);
+    write_tx_descriptor(&mut e1000, 1, descriptor);
+    let messages = capture_logs(|| write_register(&mut e1000, TDT, 2));
+
+    assert!(messages
+        .iter()
+        .any(|message| message.contains("Bad TX0 descriptor at index 0 (unknown DTYP 15)")));
+    assert_eq!(e1000.nic_ctx.sent, [vec![1u8; 60]]);
+    assert_eq!(e1000.tx_head(0), Some(2));
+}
+
+#[test]
+fn receive_overrun_counts_no_buffer() {
+    let mut e1000 = new_device(Model::I82540EM);
+    // Three descriptors, one of them kept in reserve
+    setup_rx_ring(&mut e1000, 0, 3);
+
+    let frame = broadcast_frame(60);
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::Received);
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::Received);
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::NoBuffer);
+    assert_eq!(e1000.receive(&frame).unwrap(), ReceiveOutcome::NoBuffer);
+
+    assert_eq!(read_register(&mut e1000, RNBC), 2);
+    assert_eq!(read_register(&mut e1000, MPC), 2);
+    assert_eq!(read_register(&mut e1000, RNBC), 0); // Clear on read
+    assert_eq!(e1000.rx_head(0), Some(2));
+}
+
+#[test]
+fn receive_while_offline_is_dropped() {
+    let mut e1000 = new_device(Model::I82540EM);
+
+    let outcome = e1000.receive(&broadcast_frame(60)).unwrap();
+
+    assert_eq!(outcome, ReceiveOutcome::Offline);
+    assert_eq!(read_register(&mut e1000, MPC), 1);
+}
+
+#[test]
+fn transmit_holds_frame_until_eop() {
+    let mut e1000 = new_device(Model::I82540EM);
+    setup_tx_ring(&mut e1000);
+
+    for index in 0..2 {
+        let descriptor = legacy_descriptor(&mut e1000, index, 100, CMD_IFCS);
+        write_tx_descriptor(&mut e1000, index, descriptor);
+    }
+    write_register(&mut e1000, TDT, 2);
+    assert!(e1000.nic_ctx.sent.is_empty());
+    assert_eq!(e1000.tx_head(0), Some(2));
+
+    let descriptor = legacy_descriptor(&mut e1000, 2, 50, CMD_EOP | CMD_IFCS | CMD_RS);
+    write_tx_descriptor(&mut e1000, 2, descriptor);
+    write_register(&mut e1000, TDT, 3);
+
+    let mut expected = vec![0u8; 100];
+    expected.extend_from_slice(&[1; 100]);
+    expected.extend_from_slice(&[2; 50]);
+    assert_eq!(e1000.nic_ctx.sent, [expected]);
+    assert_eq!(e1000.tx_descriptor_done(0, 2), Some(true));
+}